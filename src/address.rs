@@ -0,0 +1,178 @@
+use crate::config::Config;
+use crate::config::DefaultAddressConfigError;
+use crate::config::Target;
+use crate::load::NetLoadError;
+
+use std::env::var;
+use std::fmt;
+use std::time::Duration;
+
+// ---------- Getting address ----------
+
+pub const ENV_VAR_NAME: &str = "WIILOAD"; // Same variable as devkitPro's wiiload
+
+/// Where the address used for connecting was found
+pub enum AddressSource {
+    Argument,
+    Target(String),
+    Environment,
+    DefaultTarget(String),
+}
+
+impl fmt::Display for AddressSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressSource::Argument => write!(f, "command line argument"),
+            AddressSource::Target(n) => write!(f, "target \"{}\"", n),
+            AddressSource::Environment => write!(f, "{} environment variable", ENV_VAR_NAME),
+            AddressSource::DefaultTarget(n) => write!(f, "default target \"{}\"", n),
+        }
+    }
+}
+
+/// Address and settings to use for connecting, all only set if the source specified them
+pub struct ResolvedAddress {
+    pub address: String,
+    pub port: Option<u16>,
+    pub source: AddressSource,
+    pub compression_level: Option<u8>,
+    pub connect_timeout: Option<Duration>,
+    pub io_timeout: Option<Duration>,
+    /// WIILOAD value that is not a network address, skipped for the default target
+    pub ignored_environment: Option<String>,
+}
+
+impl ResolvedAddress {
+    fn from_parts(address: String, port: Option<u16>, source: AddressSource) -> ResolvedAddress {
+        ResolvedAddress {
+            address,
+            port,
+            source,
+            compression_level: None,
+            connect_timeout: None,
+            io_timeout: None,
+            ignored_environment: None,
+        }
+    }
+
+    /// Splits the port from the address, if any
+    fn new(address: &str, source: AddressSource) -> Option<ResolvedAddress> {
+        let (address, port) = split_host_port(address)?;
+        Some(ResolvedAddress::from_parts(address, port, source))
+    }
+
+    fn from_target(
+        target: &Target,
+        source: AddressSource,
+    ) -> Result<ResolvedAddress, DefaultAddressConfigError> {
+        let mut resolved = match ResolvedAddress::new(&target.address, source) {
+            Some(r) => r,
+            None => {
+                return Err(DefaultAddressConfigError::InvalidValue(
+                    target.address.clone(),
+                ))
+            }
+        };
+        resolved.port = target.port.or(resolved.port);
+        resolved.compression_level = target.compression_level()?;
+        resolved.connect_timeout = target.connect_timeout()?;
+        resolved.io_timeout = target.io_timeout()?;
+        Ok(resolved)
+    }
+}
+
+/// Splits "host", "host:port", "[ipv6]" or "[ipv6]:port". A bare IPv6 address is returned as the host.
+pub fn split_host_port(s: &str) -> Option<(String, Option<u16>)> {
+    if let Some(rest) = s.strip_prefix('[') {
+        let end = rest.find(']')?;
+        let port = match &rest[end + 1..] {
+            "" => None,
+            p => Some(p.strip_prefix(':')?.parse().ok()?),
+        };
+        return Some((rest[..end].to_string(), port));
+    }
+
+    let mut parts = s.split(':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (_, Some(_), Some(_)) => Some((s.to_string(), None)), // Bare IPv6
+        (Some(""), _, _) => None,
+        (Some(host), Some(port), None) => Some((host.to_string(), Some(port.parse().ok()?))),
+        _ => Some((s.to_string(), None)),
+    }
+}
+
+/// Opposite of split_host_port, putting IPv6 addresses in brackets so that the port stays apart
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// What the WIILOAD environment variable holds
+enum EnvAddress {
+    Unset,
+    Address(String, Option<u16>),
+    /// Not meant for the network, such as a USB Gecko device for devkitPro's wiiload
+    Other(String),
+}
+
+fn get_env_address() -> Result<EnvAddress, DefaultAddressConfigError> {
+    let value = match var(ENV_VAR_NAME) {
+        Ok(v) if !v.is_empty() => v,
+        _ => return Ok(EnvAddress::Unset),
+    };
+
+    match value.strip_prefix("tcp:") {
+        Some(a) => match split_host_port(a) {
+            Some((address, port)) => Ok(EnvAddress::Address(address, port)),
+            None => Err(DefaultAddressConfigError::InvalidEnvironment(value)),
+        },
+        None => Ok(EnvAddress::Other(value)),
+    }
+}
+
+/// Gets the address from the argument or the named target, otherwise from the environment or the default target
+pub fn maybe_get_address(
+    config: &Config,
+    address: Option<String>,
+    target: Option<String>,
+) -> Result<ResolvedAddress, NetLoadError> {
+    if let Some(a) = address {
+        return match ResolvedAddress::new(&a, AddressSource::Argument) {
+            Some(r) => Ok(r),
+            None => Err(NetLoadError::InvalidAddress(a)),
+        };
+    }
+
+    if let Some(name) = target {
+        let t = config.target(&name)?;
+        return Ok(ResolvedAddress::from_target(
+            t,
+            AddressSource::Target(name),
+        )?);
+    }
+
+    let ignored_environment = match get_env_address()? {
+        EnvAddress::Address(address, port) => {
+            return Ok(ResolvedAddress::from_parts(
+                address,
+                port,
+                AddressSource::Environment,
+            ))
+        }
+        EnvAddress::Other(v) => Some(v),
+        EnvAddress::Unset => None,
+    };
+
+    match (config.default_target()?, ignored_environment) {
+        (Some((name, t)), ignored_environment) => Ok(ResolvedAddress {
+            ignored_environment,
+            ..ResolvedAddress::from_target(t, AddressSource::DefaultTarget(name.to_string()))?
+        }),
+        // Says more than not having any address
+        (None, Some(v)) => Err(DefaultAddressConfigError::InvalidEnvironment(v).into()),
+        (None, None) => Err(NetLoadError::NoAddressPassed),
+    }
+}
@@ -0,0 +1,77 @@
+use crate::config::get_config;
+use crate::load::transfer_time;
+use crate::load::DEFAULT_LINK_SPEED;
+use crate::Destination;
+use crate::NetLoadError;
+use crate::Payload;
+use crate::MAX_COMPRESSION_LEVEL;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use serde::Serialize;
+
+use std::iter::once;
+use std::time::Instant;
+
+// ---------- Measuring compression and link speed ----------
+
+/// How long sending at one level would take
+#[derive(Serialize)]
+pub struct LevelTiming {
+    /// None when sent uncompressed
+    pub level: Option<u8>,
+    /// Size of the binary as sent
+    pub size: usize,
+    /// In seconds, spent compressing on this computer
+    pub compression_time: f64,
+    /// In seconds, estimated from the link speed
+    pub transfer_time: f64,
+    pub total_time: f64,
+}
+
+/// Compresses at every level, estimating the total time of each with the link speed in KiB/s
+pub fn time_levels(data: &[u8], link_speed: u32) -> Vec<LevelTiming> {
+    once(None)
+        .chain((0..=MAX_COMPRESSION_LEVEL).map(Some))
+        .map(|level| {
+            let start = Instant::now();
+            let size = match level {
+                Some(l) => compress_to_vec_zlib(data, l).len(),
+                None => data.len(),
+            };
+            let compression_time = start.elapsed().as_secs_f64();
+            let transfer_time = transfer_time(size, link_speed).as_secs_f64();
+            LevelTiming {
+                level,
+                size,
+                compression_time,
+                transfer_time,
+                total_time: compression_time + transfer_time,
+            }
+        })
+        .collect()
+}
+
+/// Timing with the lowest total time
+pub fn fastest(timings: &[LevelTiming]) -> Option<&LevelTiming> {
+    timings
+        .iter()
+        .min_by(|a, b| a.total_time.total_cmp(&b.total_time))
+}
+
+/// Link speed from the configuration, or the default one
+pub fn configured_link_speed() -> Result<u32, NetLoadError> {
+    Ok(get_config()?.link_speed()?.unwrap_or(DEFAULT_LINK_SPEED))
+}
+
+/// Sends the payload uncompressed and works out the speed of the link in KiB/s.
+/// The Wii runs it as any other upload. Small payloads give unreliable results, as they fit in network buffers.
+pub fn measure_link_speed(
+    destination: &Destination,
+    payload: &Payload,
+) -> Result<u32, NetLoadError> {
+    let stream = destination.encode_at(payload, None)?;
+    let transfer = destination.transmit(&stream)?;
+    let speed = transfer.sent as f64 / 1024.0 / transfer.duration.as_secs_f64();
+    // Saturates if it took no time at all
+    Ok((speed.round() as u32).max(1))
+}
@@ -0,0 +1,476 @@
+use crate::address::split_host_port;
+use crate::address::ENV_VAR_NAME;
+use crate::parse_seconds;
+use crate::MAX_COMPRESSION_LEVEL;
+
+use dirs::config_dir;
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::fs::read_to_string;
+use std::fs::remove_file;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// ---------- Config file handling ----------
+
+const FOLDER_NAME: &str = "riiload";
+const FILE_NAME: &str = "config.toml";
+const DEFAULT_TARGET_NAME: &str = "default"; // Created when setting the default address without a default target
+
+const LEGACY_FILE_NAME: &str = "riiload_config"; // Address alone, stored directly in the config folder before config.toml
+
+#[derive(Debug)]
+pub enum DefaultAddressConfigError {
+    /// "dirs" crate could not find a suitable storage location
+    NoSuitableFolder,
+    /// No configuration found
+    NoConfiguredDefault,
+    /// Could not read/write to file properly
+    FileAccess { path: PathBuf, source: IOError },
+    /// WIILOAD environment variable is set, but not to something we understand
+    InvalidEnvironment(String),
+    /// The configuration contains something that can't be used
+    InvalidValue(String),
+    /// Legacy file holds something that is not an address
+    InvalidLegacyFile { path: PathBuf, address: String },
+    /// Configuration file could not be parsed or written
+    MalformedConfig {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// No target with this name
+    UnknownTarget(String),
+    /// No target belongs to this group
+    UnknownGroup(String),
+}
+
+impl DefaultAddressConfigError {
+    fn file_access(path: &Path, source: IOError) -> DefaultAddressConfigError {
+        DefaultAddressConfigError::FileAccess {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    fn malformed<E: Error + Send + Sync + 'static>(
+        path: &Path,
+        source: E,
+    ) -> DefaultAddressConfigError {
+        DefaultAddressConfigError::MalformedConfig {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        }
+    }
+}
+
+impl fmt::Display for DefaultAddressConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefaultAddressConfigError::NoSuitableFolder => {
+                write!(
+                    f,
+                    "Could not find a folder for storing configuration, aborting."
+                )
+            }
+            DefaultAddressConfigError::NoConfiguredDefault => {
+                write!(f, "Not configured, aborting.")
+            }
+            DefaultAddressConfigError::FileAccess { path, .. } => {
+                write!(f, "Could not access \"{}\", aborting.", path.display())
+            }
+            DefaultAddressConfigError::InvalidEnvironment(v) => write!(
+                f,
+                "{} environment variable is set to \"{}\", expected \"tcp:host[:port]\", aborting.",
+                ENV_VAR_NAME, v
+            ),
+            DefaultAddressConfigError::InvalidValue(v) => {
+                write!(f, "Invalid value \"{}\" in configuration, aborting.", v)
+            }
+            DefaultAddressConfigError::InvalidLegacyFile { path, address } => write!(
+                f,
+                "Invalid address \"{}\" in \"{}\", aborting. (\"config file delete\" removes it)",
+                address,
+                path.display()
+            ),
+            DefaultAddressConfigError::MalformedConfig { path, .. } => write!(
+                f,
+                "Configuration file \"{}\" is malformed, aborting.",
+                path.display()
+            ),
+            DefaultAddressConfigError::UnknownTarget(n) => {
+                write!(f, "No target named \"{}\", aborting.", n)
+            }
+            DefaultAddressConfigError::UnknownGroup(n) => {
+                write!(f, "No target in group \"{}\", aborting.", n)
+            }
+        }
+    }
+}
+
+impl Error for DefaultAddressConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DefaultAddressConfigError::FileAccess { source, .. } => Some(source),
+            DefaultAddressConfigError::MalformedConfig { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl DefaultAddressConfigError {
+    /// Short identifier for --json output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DefaultAddressConfigError::NoSuitableFolder => "no_config_folder",
+            DefaultAddressConfigError::NoConfiguredDefault => "not_configured",
+            DefaultAddressConfigError::FileAccess { .. } => "config_file_access",
+            DefaultAddressConfigError::InvalidEnvironment(_) => "invalid_environment",
+            DefaultAddressConfigError::InvalidValue(_) => "invalid_config_value",
+            DefaultAddressConfigError::InvalidLegacyFile { .. } => "invalid_config_value",
+            DefaultAddressConfigError::MalformedConfig { .. } => "malformed_config",
+            DefaultAddressConfigError::UnknownTarget(_) => "unknown_target",
+            DefaultAddressConfigError::UnknownGroup(_) => "unknown_group",
+        }
+    }
+
+    /// Process exit code, see EXIT_CODES in main.rs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DefaultAddressConfigError::NoSuitableFolder => 30,
+            DefaultAddressConfigError::NoConfiguredDefault => 31,
+            DefaultAddressConfigError::FileAccess { .. } => 32,
+            DefaultAddressConfigError::InvalidEnvironment(_) => 33,
+            DefaultAddressConfigError::InvalidValue(_) => 34,
+            DefaultAddressConfigError::InvalidLegacyFile { .. } => 34,
+            DefaultAddressConfigError::MalformedConfig { .. } => 35,
+            DefaultAddressConfigError::UnknownTarget(_) => 36,
+            DefaultAddressConfigError::UnknownGroup(_) => 37,
+        }
+    }
+}
+
+/// Everything stored in config.toml
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct Config {
+    /// Used when no level is given
+    pub compression_level: Option<u8>,
+    /// In KiB/s, used for picking a compression level automatically
+    pub link_speed: Option<u32>,
+    /// In seconds
+    pub connect_timeout: Option<f64>,
+    /// In seconds
+    pub io_timeout: Option<f64>,
+    /// Name of the target used when no address is given
+    pub default_target: Option<String>,
+    #[serde(default)]
+    pub targets: BTreeMap<String, Target>,
+}
+
+impl Config {
+    pub fn compression_level(&self) -> Result<Option<u8>, DefaultAddressConfigError> {
+        check_compression_level(self.compression_level)
+    }
+
+    pub fn link_speed(&self) -> Result<Option<u32>, DefaultAddressConfigError> {
+        match self.link_speed {
+            Some(0) => Err(DefaultAddressConfigError::InvalidValue(0.to_string())),
+            s => Ok(s),
+        }
+    }
+
+    pub fn connect_timeout(&self) -> Result<Option<Duration>, DefaultAddressConfigError> {
+        self.connect_timeout.map(seconds_to_duration).transpose()
+    }
+
+    pub fn io_timeout(&self) -> Result<Option<Duration>, DefaultAddressConfigError> {
+        self.io_timeout.map(seconds_to_duration).transpose()
+    }
+
+    pub fn target(&self, name: &str) -> Result<&Target, DefaultAddressConfigError> {
+        match self.targets.get(name) {
+            Some(t) => Ok(t),
+            None => Err(DefaultAddressConfigError::UnknownTarget(name.to_string())),
+        }
+    }
+
+    /// Names of the targets belonging to a group
+    pub fn group(&self, name: &str) -> Result<Vec<&str>, DefaultAddressConfigError> {
+        let members: Vec<&str> = self
+            .targets
+            .iter()
+            .filter(|(_, t)| t.groups.iter().any(|g| g == name))
+            .map(|(n, _)| n.as_str())
+            .collect();
+        if members.is_empty() {
+            return Err(DefaultAddressConfigError::UnknownGroup(name.to_string()));
+        }
+        Ok(members)
+    }
+
+    /// Name and settings of the default target, if there is one
+    pub fn default_target(&self) -> Result<Option<(&str, &Target)>, DefaultAddressConfigError> {
+        match &self.default_target {
+            Some(name) => Ok(Some((name, self.target(name)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds a target, replacing any existing one with the same name
+    pub fn add_target(
+        &mut self,
+        name: String,
+        target: Target,
+    ) -> Result<(), DefaultAddressConfigError> {
+        if split_host_port(&target.address).is_none() {
+            return Err(DefaultAddressConfigError::InvalidValue(target.address));
+        }
+        self.targets.insert(name, target);
+        Ok(())
+    }
+
+    pub fn remove_target(&mut self, name: &str) -> Result<(), DefaultAddressConfigError> {
+        if self.targets.remove(name).is_none() {
+            return Err(DefaultAddressConfigError::UnknownTarget(name.to_string()));
+        }
+        if self.default_target.as_deref() == Some(name) {
+            self.default_target = None;
+        }
+        Ok(())
+    }
+
+    pub fn set_default_target(&mut self, name: String) -> Result<(), DefaultAddressConfigError> {
+        self.target(&name)?;
+        self.default_target = Some(name);
+        Ok(())
+    }
+
+    /// Changes the address of the default target, creating one if needed
+    pub fn set_default_address(
+        &mut self,
+        address: String,
+    ) -> Result<(), DefaultAddressConfigError> {
+        if split_host_port(&address).is_none() {
+            return Err(DefaultAddressConfigError::InvalidValue(address));
+        }
+
+        let name = self
+            .default_target
+            .get_or_insert_with(|| DEFAULT_TARGET_NAME.to_string())
+            .clone();
+        self.targets
+            .entry(name)
+            .and_modify(|t| t.address = address.clone())
+            .or_insert_with(|| Target::new(address));
+        Ok(())
+    }
+}
+
+/// A Wii, along with settings to use when sending to it
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Target {
+    pub address: String,
+    pub port: Option<u16>,
+    pub compression_level: Option<u8>,
+    /// In seconds
+    pub connect_timeout: Option<f64>,
+    /// In seconds
+    pub io_timeout: Option<f64>,
+    /// Names that can be given to "load --group" to send to several targets at once
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl Target {
+    pub fn new(address: String) -> Target {
+        Target {
+            address,
+            port: None,
+            compression_level: None,
+            connect_timeout: None,
+            io_timeout: None,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn compression_level(&self) -> Result<Option<u8>, DefaultAddressConfigError> {
+        check_compression_level(self.compression_level)
+    }
+
+    pub fn connect_timeout(&self) -> Result<Option<Duration>, DefaultAddressConfigError> {
+        self.connect_timeout.map(seconds_to_duration).transpose()
+    }
+
+    pub fn io_timeout(&self) -> Result<Option<Duration>, DefaultAddressConfigError> {
+        self.io_timeout.map(seconds_to_duration).transpose()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(p) = self.port {
+            write!(f, " port={}", p)?;
+        }
+        if let Some(l) = self.compression_level {
+            write!(f, " level={}", l)?;
+        }
+        if let Some(t) = self.connect_timeout {
+            write!(f, " connect-timeout={}", t)?;
+        }
+        if let Some(t) = self.io_timeout {
+            write!(f, " io-timeout={}", t)?;
+        }
+        if !self.groups.is_empty() {
+            write!(f, " groups={}", self.groups.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks a compression level coming from a hand-editable file
+fn check_compression_level(level: Option<u8>) -> Result<Option<u8>, DefaultAddressConfigError> {
+    match level {
+        Some(l) if l > MAX_COMPRESSION_LEVEL => {
+            Err(DefaultAddressConfigError::InvalidValue(l.to_string()))
+        }
+        l => Ok(l),
+    }
+}
+
+/// Checks a number of seconds coming from a hand-editable file
+fn seconds_to_duration(seconds: f64) -> Result<Duration, DefaultAddressConfigError> {
+    parse_seconds(&seconds.to_string())
+        .map_err(|_| DefaultAddressConfigError::InvalidValue(seconds.to_string()))
+}
+
+fn get_config_folder() -> Result<PathBuf, DefaultAddressConfigError> {
+    match config_dir() {
+        Some(c) => Ok(c),
+        _ => Err(DefaultAddressConfigError::NoSuitableFolder),
+    }
+}
+
+pub fn get_config_path() -> Result<PathBuf, DefaultAddressConfigError> {
+    let mut config = get_config_folder()?;
+
+    config.push(FOLDER_NAME);
+    config.push(FILE_NAME);
+
+    Ok(config)
+}
+
+/// Reads a whole file, None if it does not exist
+fn read_optional(path: &Path) -> Result<Option<String>, DefaultAddressConfigError> {
+    match read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == IOErrorKind::NotFound => Ok(None),
+        Err(e) => Err(DefaultAddressConfigError::file_access(path, e)),
+    }
+}
+
+/// Moves the address of the file used before config.toml over to it, if there is one
+fn migrate_legacy_file(folder: &Path) -> Result<Option<Config>, DefaultAddressConfigError> {
+    let path = folder.join(LEGACY_FILE_NAME);
+    let address = match read_optional(&path)? {
+        // Hand-edited files often end with a newline
+        Some(a) => a.trim().to_string(),
+        None => return Ok(None),
+    };
+
+    // Emptied by hand, nothing is configured then
+    let config = if address.is_empty() {
+        None
+    } else if split_host_port(&address).is_none() {
+        return Err(DefaultAddressConfigError::InvalidLegacyFile { path, address });
+    } else {
+        let mut config = Config::default();
+        config.set_default_address(address)?;
+        set_config(&config)?;
+        Some(config)
+    };
+    remove_file(&path).map_err(|e| DefaultAddressConfigError::file_access(&path, e))?;
+
+    Ok(config)
+}
+
+/// Reads config.toml as is, None if it does not exist
+fn read_config_file() -> Result<Option<Config>, DefaultAddressConfigError> {
+    let path = get_config_path()?;
+    match read_optional(&path)? {
+        Some(s) => toml::from_str(&s)
+            .map(Some)
+            .map_err(|e| DefaultAddressConfigError::malformed(&path, e)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the configuration, migrating the legacy file on first run. Empty if nothing is configured.
+pub fn get_config() -> Result<Config, DefaultAddressConfigError> {
+    if let Some(c) = read_config_file()? {
+        return Ok(c);
+    }
+
+    Ok(migrate_legacy_file(&get_config_folder()?)?.unwrap_or_default())
+}
+
+/// Same as get_config, but leaves the legacy file alone as only settings other than the address are needed
+pub fn get_settings() -> Result<Config, DefaultAddressConfigError> {
+    Ok(read_config_file()?.unwrap_or_default())
+}
+
+pub fn set_config(config: &Config) -> Result<(), DefaultAddressConfigError> {
+    let path = get_config_path()?;
+    let serialized =
+        toml::to_string(config).map_err(|e| DefaultAddressConfigError::malformed(&path, e))?;
+
+    if let Some(folder) = path.parent() {
+        create_dir_all(folder).map_err(|e| DefaultAddressConfigError::file_access(folder, e))?;
+    }
+    File::create(&path)
+        .and_then(|mut w| w.write_all(serialized.as_bytes()))
+        .map_err(|e| DefaultAddressConfigError::file_access(&path, e))?;
+
+    Ok(())
+}
+
+/// Address of the default target
+pub fn get_default_address() -> Result<String, DefaultAddressConfigError> {
+    match get_config()?.default_target()? {
+        Some((_, t)) => Ok(t.address.clone()),
+        None => Err(DefaultAddressConfigError::NoConfiguredDefault),
+    }
+}
+
+pub fn set_default_address(new: String) -> Result<(), DefaultAddressConfigError> {
+    let mut config = get_config()?;
+    config.set_default_address(new)?;
+    set_config(&config)
+}
+
+/// Deletes config.toml and the legacy file without reading them, so that broken ones can go too
+pub fn remove_config_files() -> Result<(), DefaultAddressConfigError> {
+    let legacy = get_config_folder()?.join(LEGACY_FILE_NAME);
+    let mut removed = false;
+    for path in &[get_config_path()?, legacy] {
+        match remove_file(path) {
+            Ok(()) => removed = true,
+            Err(e) if e.kind() == IOErrorKind::NotFound => {}
+            Err(e) => return Err(DefaultAddressConfigError::file_access(path, e)),
+        }
+    }
+
+    if !removed {
+        return Err(DefaultAddressConfigError::NoConfiguredDefault);
+    }
+    Ok(())
+}
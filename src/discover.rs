@@ -0,0 +1,97 @@
+use if_addrs::get_if_addrs;
+use if_addrs::IfAddr;
+use serde::Serialize;
+
+use std::io::Error as IOError;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// ---------- Looking for Wiis on the local network ----------
+
+const PARALLEL_PROBES: usize = 64;
+const MIN_PREFIX_LENGTH: u32 = 22; // Larger subnets would take too long, only the /24 around us is scanned then
+
+/// Host that accepted a connection on the probed port
+#[derive(Serialize)]
+pub struct Responder {
+    pub address: Ipv4Addr,
+    pub port: u16,
+    /// Name of the interface whose subnet contains this host
+    pub interface: String,
+}
+
+/// Lists the hosts to probe for every non-loopback IPv4 interface, along with the interface name
+fn candidates() -> Result<Vec<(Ipv4Addr, String)>, IOError> {
+    let mut hosts = Vec::new();
+
+    for interface in get_if_addrs()? {
+        if interface.is_loopback() {
+            continue;
+        }
+        let v4 = match interface.addr {
+            IfAddr::V4(v4) => v4,
+            IfAddr::V6(_) => continue,
+        };
+
+        let own = u32::from(v4.ip);
+        let mut mask = u32::from(v4.netmask);
+        if mask.count_ones() < MIN_PREFIX_LENGTH {
+            mask = !0xFF;
+        }
+        let network = own & mask;
+        let broadcast = network | !mask;
+
+        for host in network.saturating_add(1)..broadcast {
+            if host != own {
+                hosts.push((Ipv4Addr::from(host), interface.name.clone()));
+            }
+        }
+    }
+
+    hosts.sort_by_key(|h| h.0);
+    hosts.dedup_by_key(|h| h.0);
+    Ok(hosts)
+}
+
+/// Tries to connect to every host of the local subnets, returning the ones that accepted
+pub fn discover(port: u16, timeout: Duration) -> Result<Vec<Responder>, IOError> {
+    let queue = Arc::new(Mutex::new(candidates()?.into_iter()));
+    let (sender, receiver) = channel();
+
+    let workers: Vec<_> = (0..PARALLEL_PROBES)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let (address, interface) = match next {
+                    Some(c) => c,
+                    None => break,
+                };
+                let sock_addr = SocketAddr::from((address, port));
+                if TcpStream::connect_timeout(&sock_addr, timeout).is_ok() {
+                    let _ = sender.send(Responder {
+                        address,
+                        port,
+                        interface,
+                    });
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut responders: Vec<Responder> = receiver.iter().collect();
+    responders.sort_by_key(|r| r.address);
+    Ok(responders)
+}
@@ -0,0 +1,511 @@
+use serde::Serialize;
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+// ---------- DOL/ELF parsing and validation ----------
+
+const DOL_HEADER_LENGTH: usize = 0x100;
+const DOL_TEXT_SECTIONS: usize = 7;
+const DOL_DATA_SECTIONS: usize = 11;
+
+const ELF_MAGIC: &[u8] = b"\x7FELF";
+const ELF_HEADER_LENGTH: usize = 0x34;
+const ELF_PROGRAM_HEADER_LENGTH: usize = 0x20;
+const ELFCLASS32: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const ET_EXEC: u16 = 2;
+const EM_PPC: u16 = 20;
+const PT_LOAD: u32 = 1;
+
+/// Physical ranges of the main memories, MEM2 only exists on the Wii
+const MEM1: (u32, u32) = (0x0000_0000, 0x0180_0000);
+const MEM2: (u32, u32) = (0x1000_0000, 0x1400_0000);
+
+#[derive(Debug)]
+pub enum ExecutableError {
+    /// File ends before a header or section does
+    Truncated,
+    /// Neither an ELF nor something that looks like a DOL
+    UnknownFormat,
+    /// ELF is not 32-bit big-endian
+    WrongElfFlavor,
+    /// ELF is for another architecture, holds the e_machine value
+    WrongMachine(u16),
+    /// ELF is an object file or shared library, holds the e_type value
+    NotExecutable(u16),
+    /// Nothing would get loaded in memory
+    NothingToLoad,
+    /// A section or segment would be loaded outside of MEM1/MEM2, holds its address
+    OutsideMemory(u32),
+    /// Entry point is not in any loaded code, holds its address
+    BadEntryPoint(u32),
+}
+
+impl fmt::Display for ExecutableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecutableError::Truncated => write!(f, "File is truncated"),
+            ExecutableError::UnknownFormat => write!(f, "File is neither an ELF nor a DOL"),
+            ExecutableError::WrongElfFlavor => write!(f, "ELF is not 32-bit big-endian"),
+            ExecutableError::WrongMachine(m) => {
+                write!(f, "ELF is not for PowerPC (machine {})", m)
+            }
+            ExecutableError::NotExecutable(t) => {
+                write!(
+                    f,
+                    "ELF is not an executable, maybe an object file (type {})",
+                    t
+                )
+            }
+            ExecutableError::NothingToLoad => write!(f, "Executable has nothing to load"),
+            ExecutableError::OutsideMemory(a) => {
+                write!(
+                    f,
+                    "Executable loads at {:#010X}, outside of the Wii's memory",
+                    a
+                )
+            }
+            ExecutableError::BadEntryPoint(a) => {
+                write!(f, "Entry point {:#010X} is not in any loaded code", a)
+            }
+        }
+    }
+}
+
+impl Error for ExecutableError {}
+
+#[derive(Serialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub enum Executable {
+    Dol(Dol),
+    Elf(Elf),
+}
+
+/// Non-empty section of a DOL
+#[derive(Serialize)]
+pub struct DolSection {
+    /// Index among text or data sections
+    pub index: usize,
+    pub offset: u32,
+    pub address: u32,
+    pub size: u32,
+}
+
+#[derive(Serialize)]
+pub struct Dol {
+    pub text: Vec<DolSection>,
+    pub data: Vec<DolSection>,
+    pub bss_address: u32,
+    pub bss_size: u32,
+    pub entry_point: u32,
+}
+
+#[derive(Serialize)]
+pub struct ElfSegment {
+    pub kind: u32,
+    pub offset: u32,
+    pub virtual_address: u32,
+    pub physical_address: u32,
+    pub file_size: u32,
+    pub memory_size: u32,
+    pub flags: u32,
+}
+
+impl ElfSegment {
+    pub fn is_loaded(&self) -> bool {
+        self.kind == PT_LOAD && self.memory_size > 0
+    }
+
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            0 => "NULL",
+            PT_LOAD => "LOAD",
+            2 => "DYNAMIC",
+            3 => "INTERP",
+            4 => "NOTE",
+            6 => "PHDR",
+            7 => "TLS",
+            _ => "OTHER",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Elf {
+    pub entry_point: u32,
+    pub segments: Vec<ElfSegment>,
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, ExecutableError> {
+    match data.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes(b.try_into().unwrap())),
+        None => Err(ExecutableError::Truncated),
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, ExecutableError> {
+    match data.get(at..at + 4) {
+        Some(b) => Ok(u32::from_be_bytes(b.try_into().unwrap())),
+        None => Err(ExecutableError::Truncated),
+    }
+}
+
+fn write_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Maps cached (0x8...) and uncached (0xC...) addresses to physical ones
+fn to_physical(address: u32) -> u32 {
+    match address & 0xE000_0000 {
+        0x8000_0000 | 0xC000_0000 => address & 0x1FFF_FFFF,
+        _ => address,
+    }
+}
+
+/// Whether the range lands in MEM2, which the GameCube does not have
+fn in_mem2(address: u32, size: u32) -> bool {
+    let start = to_physical(address);
+    size > 0 && start >= MEM2.0 && start < MEM2.1
+}
+
+/// Whether the whole range fits in MEM1 or MEM2
+fn in_memory(address: u32, size: u32) -> bool {
+    let start = to_physical(address) as u64;
+    let end = start + size as u64;
+    [MEM1, MEM2]
+        .iter()
+        .any(|&(low, high)| start >= low as u64 && end <= high as u64)
+}
+
+fn check_in_file(data: &[u8], offset: u32, size: u32) -> Result<(), ExecutableError> {
+    if offset as u64 + size as u64 > data.len() as u64 {
+        return Err(ExecutableError::Truncated);
+    }
+    Ok(())
+}
+
+impl Dol {
+    fn parse(data: &[u8]) -> Result<Dol, ExecutableError> {
+        if data.len() < DOL_HEADER_LENGTH {
+            return Err(ExecutableError::UnknownFormat);
+        }
+
+        let sections = |first: usize, count: usize| -> Result<Vec<DolSection>, ExecutableError> {
+            let mut sections = Vec::new();
+            for index in 0..count {
+                let section = DolSection {
+                    index,
+                    offset: read_u32(data, (first + index) * 4)?,
+                    address: read_u32(data, 0x48 + (first + index) * 4)?,
+                    size: read_u32(data, 0x90 + (first + index) * 4)?,
+                };
+                if section.size == 0 {
+                    continue;
+                }
+                // Anything else is very unlikely to be a DOL at all
+                if (section.offset as usize) < DOL_HEADER_LENGTH
+                    || check_in_file(data, section.offset, section.size).is_err()
+                {
+                    return Err(ExecutableError::UnknownFormat);
+                }
+                if !in_memory(section.address, section.size) {
+                    return Err(ExecutableError::OutsideMemory(section.address));
+                }
+                sections.push(section);
+            }
+            Ok(sections)
+        };
+
+        let dol = Dol {
+            text: sections(0, DOL_TEXT_SECTIONS)?,
+            data: sections(DOL_TEXT_SECTIONS, DOL_DATA_SECTIONS)?,
+            bss_address: read_u32(data, 0xD8)?,
+            bss_size: read_u32(data, 0xDC)?,
+            entry_point: read_u32(data, 0xE0)?,
+        };
+
+        if dol.text.is_empty() {
+            return Err(ExecutableError::NothingToLoad);
+        }
+        let in_text = dol
+            .text
+            .iter()
+            .any(|s| dol.entry_point >= s.address && dol.entry_point - s.address < s.size);
+        if !in_text {
+            return Err(ExecutableError::BadEntryPoint(dol.entry_point));
+        }
+
+        Ok(dol)
+    }
+}
+
+impl Elf {
+    fn parse(data: &[u8]) -> Result<Elf, ExecutableError> {
+        if data.len() < ELF_HEADER_LENGTH {
+            return Err(ExecutableError::Truncated);
+        }
+        if data[4] != ELFCLASS32 || data[5] != ELFDATA2MSB {
+            return Err(ExecutableError::WrongElfFlavor);
+        }
+        let machine = read_u16(data, 0x12)?;
+        if machine != EM_PPC {
+            return Err(ExecutableError::WrongMachine(machine));
+        }
+        let kind = read_u16(data, 0x10)?;
+        if kind != ET_EXEC {
+            return Err(ExecutableError::NotExecutable(kind));
+        }
+
+        let entry_point = read_u32(data, 0x18)?;
+        let program_headers = read_u32(data, 0x1C)? as usize;
+        let entry_size = (read_u16(data, 0x2A)? as usize).max(ELF_PROGRAM_HEADER_LENGTH);
+        let count = read_u16(data, 0x2C)? as usize;
+
+        let mut segments = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = program_headers + i * entry_size;
+            let segment = ElfSegment {
+                kind: read_u32(data, at)?,
+                offset: read_u32(data, at + 0x04)?,
+                virtual_address: read_u32(data, at + 0x08)?,
+                physical_address: read_u32(data, at + 0x0C)?,
+                file_size: read_u32(data, at + 0x10)?,
+                memory_size: read_u32(data, at + 0x14)?,
+                flags: read_u32(data, at + 0x18)?,
+            };
+            if segment.is_loaded() {
+                check_in_file(data, segment.offset, segment.file_size)?;
+                if !in_memory(segment.physical_address, segment.memory_size) {
+                    return Err(ExecutableError::OutsideMemory(segment.physical_address));
+                }
+            }
+            segments.push(segment);
+        }
+
+        let elf = Elf {
+            entry_point,
+            segments,
+        };
+        let loaded: Vec<&ElfSegment> = elf.segments.iter().filter(|s| s.is_loaded()).collect();
+        if loaded.is_empty() {
+            return Err(ExecutableError::NothingToLoad);
+        }
+        let in_loaded = loaded.iter().any(|s| {
+            entry_point >= s.virtual_address && entry_point - s.virtual_address < s.memory_size
+        });
+        if !in_loaded {
+            return Err(ExecutableError::BadEntryPoint(entry_point));
+        }
+
+        Ok(elf)
+    }
+}
+
+impl Executable {
+    /// Parses a DOL or an ELF, refusing anything that the Wii could not run
+    pub fn parse(data: &[u8]) -> Result<Executable, ExecutableError> {
+        if data.starts_with(ELF_MAGIC) {
+            Ok(Executable::Elf(Elf::parse(data)?))
+        } else {
+            Ok(Executable::Dol(Dol::parse(data)?))
+        }
+    }
+}
+
+/// Where an executable ends up in memory once loaded
+#[derive(Serialize)]
+pub struct Layout {
+    /// Lowest address anything is loaded to
+    pub start: u32,
+    /// Address right after the last loaded byte
+    pub end: u32,
+    /// Bytes actually occupied, BSS included
+    pub size: u64,
+    /// Only the Wii has MEM2, anything else could also run on a GameCube
+    pub uses_mem2: bool,
+}
+
+impl Executable {
+    pub fn entry_point(&self) -> u32 {
+        match self {
+            Executable::Dol(d) => d.entry_point,
+            Executable::Elf(e) => e.entry_point,
+        }
+    }
+
+    /// Address and size of everything that gets loaded, BSS included
+    fn loaded_ranges(&self) -> Vec<(u32, u32)> {
+        match self {
+            Executable::Dol(d) => d
+                .text
+                .iter()
+                .chain(&d.data)
+                .map(|s| (s.address, s.size))
+                .chain(Some((d.bss_address, d.bss_size)).filter(|b| b.1 > 0))
+                .collect(),
+            Executable::Elf(e) => e
+                .segments
+                .iter()
+                .filter(|s| s.is_loaded())
+                .map(|s| (s.virtual_address, s.memory_size))
+                .collect(),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        let ranges = self.loaded_ranges();
+        Layout {
+            start: ranges.iter().map(|r| r.0).min().unwrap_or(0),
+            end: ranges
+                .iter()
+                .map(|r| r.0.saturating_add(r.1))
+                .max()
+                .unwrap_or(0),
+            size: ranges.iter().map(|r| r.1 as u64).sum(),
+            uses_mem2: ranges.iter().any(|&(a, s)| in_mem2(a, s)),
+        }
+    }
+}
+
+impl fmt::Display for Executable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Executable::Dol(d) => write!(
+                f,
+                "DOL, {} text and {} data sections, BSS of {} bytes at {:#010X}, entry point {:#010X}",
+                d.text.len(),
+                d.data.len(),
+                d.bss_size,
+                d.bss_address,
+                d.entry_point
+            ),
+            Executable::Elf(e) => write!(
+                f,
+                "ELF, {} loadable segments out of {}, entry point {:#010X}",
+                e.segments.iter().filter(|s| s.is_loaded()).count(),
+                e.segments.len(),
+                e.entry_point
+            ),
+        }
+    }
+}
+
+// ---------- Converting ELF to DOL ----------
+
+/// Segment flag for code
+const PF_X: u32 = 1;
+/// Sections start at multiples of this in the file, as elf2dol does
+const DOL_SECTION_ALIGNMENT: usize = 32;
+
+#[derive(Debug)]
+pub enum ConvertError {
+    /// Not a valid ELF, or the resulting DOL would not be valid
+    InvalidExecutable(ExecutableError),
+    /// File already is a DOL
+    NotElf,
+    /// More code segments than a DOL has text sections, holds their count
+    TooManyTextSegments(usize),
+    /// More data segments than a DOL has data sections, holds their count
+    TooManyDataSegments(usize),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::InvalidExecutable(_) => write!(f, "Executable cannot be converted"),
+            ConvertError::NotElf => write!(f, "File already is a DOL"),
+            ConvertError::TooManyTextSegments(n) => write!(
+                f,
+                "ELF has {} code segments, a DOL can only hold {}",
+                n, DOL_TEXT_SECTIONS
+            ),
+            ConvertError::TooManyDataSegments(n) => write!(
+                f,
+                "ELF has {} data segments, a DOL can only hold {}",
+                n, DOL_DATA_SECTIONS
+            ),
+        }
+    }
+}
+
+impl Error for ConvertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConvertError::InvalidExecutable(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExecutableError> for ConvertError {
+    fn from(e: ExecutableError) -> ConvertError {
+        ConvertError::InvalidExecutable(e)
+    }
+}
+
+impl Elf {
+    /// Lays loadable segments out as DOL sections, code as text and the rest as data.
+    /// Memory reserved past what the file holds becomes a single BSS range spanning all of it.
+    /// data must be the file this was parsed from.
+    fn to_dol(&self, data: &[u8]) -> Result<Vec<u8>, ConvertError> {
+        let (text, other): (Vec<&ElfSegment>, Vec<&ElfSegment>) = self
+            .segments
+            .iter()
+            .filter(|s| s.is_loaded() && s.file_size > 0)
+            .partition(|s| s.flags & PF_X != 0);
+        if text.len() > DOL_TEXT_SECTIONS {
+            return Err(ConvertError::TooManyTextSegments(text.len()));
+        }
+        if other.len() > DOL_DATA_SECTIONS {
+            return Err(ConvertError::TooManyDataSegments(other.len()));
+        }
+
+        let bss: Vec<(u32, u32)> = self
+            .segments
+            .iter()
+            .filter(|s| s.is_loaded() && s.memory_size > s.file_size)
+            .map(|s| {
+                (
+                    s.virtual_address.saturating_add(s.file_size),
+                    s.virtual_address.saturating_add(s.memory_size),
+                )
+            })
+            .collect();
+        let bss_address = bss.iter().map(|b| b.0).min().unwrap_or(0);
+        let bss_end = bss.iter().map(|b| b.1).max().unwrap_or(0);
+
+        let mut dol = vec![0; DOL_HEADER_LENGTH];
+        let slots = text.iter().enumerate().chain(
+            other
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (DOL_TEXT_SECTIONS + i, s)),
+        );
+        for (slot, segment) in slots {
+            let offset = dol.len().div_ceil(DOL_SECTION_ALIGNMENT) * DOL_SECTION_ALIGNMENT;
+            dol.resize(offset, 0);
+            write_u32(&mut dol, slot * 4, offset as u32);
+            write_u32(&mut dol, 0x48 + slot * 4, segment.virtual_address);
+            write_u32(&mut dol, 0x90 + slot * 4, segment.file_size);
+            let start = segment.offset as usize;
+            dol.extend_from_slice(&data[start..start + segment.file_size as usize]);
+        }
+        write_u32(&mut dol, 0xD8, bss_address);
+        write_u32(&mut dol, 0xDC, bss_end - bss_address);
+        write_u32(&mut dol, 0xE0, self.entry_point);
+
+        // Catches an entry point outside of code, which an ELF allows
+        Dol::parse(&dol)?;
+        Ok(dol)
+    }
+}
+
+/// Converts an ELF file to a DOL, checking both
+pub fn elf_to_dol(data: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    match Executable::parse(data)? {
+        Executable::Elf(elf) => elf.to_dol(data),
+        Executable::Dol(_) => Err(ConvertError::NotElf),
+    }
+}
@@ -0,0 +1,92 @@
+//! Sends executables to a Wii running the Homebrew Channel over the network, as the riiload command does.
+//!
+//! ```no_run
+//! use riiload::Loader;
+//! use riiload::Payload;
+//! use riiload::PayloadOptions;
+//!
+//! let payload = Payload::read(&PayloadOptions {
+//!     path: "boot.dol".to_string(),
+//!     name: None,
+//!     install: false,
+//!     force: false,
+//!     to_dol: false,
+//! })?;
+//! let transfer = Loader::new("192.168.1.20").load(&payload)?;
+//! println!("Sent {} bytes", transfer.sent);
+//! # Ok::<(), riiload::NetLoadError>(())
+//! ```
+
+pub mod bench;
+pub mod config;
+pub mod discover;
+pub mod executable;
+pub mod package;
+pub mod progress;
+pub mod receive;
+
+mod address;
+mod load;
+
+pub use address::join_host_port;
+pub use address::split_host_port;
+pub use address::AddressSource;
+pub use address::ENV_VAR_NAME;
+pub use config::Target;
+pub use load::is_stdin;
+pub use load::load_all;
+pub use load::send_all;
+pub use load::Compression;
+pub use load::Delivery;
+pub use load::Destination;
+pub use load::Encoded;
+pub use load::Loader;
+pub use load::NetLoadError;
+pub use load::OnRetry;
+pub use load::Payload;
+pub use load::PayloadOptions;
+pub use load::Retry;
+
+use std::time::Duration;
+
+pub const MAX_COMPRESSION_LEVEL: u8 = 9;
+pub const TCP_PORT: u16 = 4299; // What the HBC listens on, but tunnels and port forwarding may use something else
+
+// ---------- Parsing values given by the user ----------
+
+pub fn parse_compression_level(s: &str) -> Result<u8, String> {
+    match s.parse::<u8>() {
+        Ok(l) if l <= MAX_COMPRESSION_LEVEL => Ok(l),
+        _ => Err(format!(
+            "compression level must be a number from 0 to {}",
+            MAX_COMPRESSION_LEVEL
+        )),
+    }
+}
+
+/// Checks the address can be split into host and port, keeping it as given
+pub fn parse_address(s: &str) -> Result<String, String> {
+    match split_host_port(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err("address must be \"host\", \"host:port\" or \"[ipv6]:port\"".to_string()),
+    }
+}
+
+pub fn parse_link_speed(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(s) if s > 0 => Ok(s),
+        _ => Err("link speed must be a positive number of KiB/s".to_string()),
+    }
+}
+
+pub fn parse_seconds(s: &str) -> Result<Duration, String> {
+    // Too small values would round down to no timeout at all
+    match s
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+    {
+        Some(d) if !d.is_zero() => Ok(d),
+        _ => Err("must be a positive number of seconds".to_string()),
+    }
+}
@@ -0,0 +1,1013 @@
+use crate::address::join_host_port;
+use crate::address::maybe_get_address;
+use crate::address::AddressSource;
+use crate::address::ENV_VAR_NAME;
+use crate::config::get_config;
+use crate::config::get_settings;
+use crate::config::Config;
+use crate::config::DefaultAddressConfigError;
+use crate::executable::elf_to_dol;
+use crate::executable::ConvertError;
+use crate::executable::Executable;
+use crate::executable::ExecutableError;
+use crate::package::Package;
+use crate::package::PackageError;
+use crate::progress::ProgressWriter;
+use crate::progress::Transfer;
+use crate::receive::Header;
+use crate::receive::ReceiveError;
+use crate::TCP_PORT;
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use wiiload_proto::net_send;
+use wiiload_proto::WiiLoadFail;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::read as fsread;
+use std::io::stdin;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+// ---------- Code for net loading ----------
+
+#[derive(Debug)]
+pub enum NetLoadError {
+    NoAddressPassed,
+    /// Address argument could not be split into host and port
+    InvalidAddress(String),
+    CantResolveAddress,
+    ArgsTooLong {
+        length: usize,
+        max: usize,
+    },
+    BinaryTooLong,
+    /// Executable or app to install could not be read
+    ReadFailed {
+        path: String,
+        source: IOError,
+    },
+    /// File is not a valid Wii executable
+    InvalidExecutable {
+        path: String,
+        source: ExecutableError,
+    },
+    /// App folder or ZIP is not something HBC can install
+    InvalidPackage {
+        path: String,
+        source: PackageError,
+    },
+    /// ELF could not be turned into a DOL
+    ConversionFailed {
+        path: String,
+        source: ConvertError,
+    },
+    /// Captured stream is not a complete upload
+    InvalidStream {
+        path: String,
+        source: ReceiveError,
+    },
+    /// Converted executable or captured stream could not be written
+    WriteFailed {
+        path: String,
+        source: IOError,
+    },
+    /// Wii did not answer in time
+    Timeout {
+        after: Duration,
+        connecting: bool,
+    },
+    /// Every resolved address failed, with the reason for each
+    ConnectFailed(Vec<(SocketAddr, IOError)>),
+    IOError(IOError),
+    OtherConfigError(DefaultAddressConfigError),
+}
+
+impl NetLoadError {
+    /// Converts a failure from wiiload-proto, using the length of the arguments that were sent for reporting.
+    fn from_send_fail(r: WiiLoadFail, args_length: usize) -> NetLoadError {
+        match r {
+            WiiLoadFail::ArgsTooLong => NetLoadError::ArgsTooLong {
+                length: args_length,
+                max: MAX_ARGS_LENGTH,
+            },
+            WiiLoadFail::BinaryTooLong => NetLoadError::BinaryTooLong,
+            WiiLoadFail::NetError(e) => NetLoadError::IOError(e),
+        }
+    }
+
+    /// Reports a timeout as such if there was only one address to try
+    fn from_connect_failures(
+        failures: Vec<(SocketAddr, IOError)>,
+        connect_timeout: Duration,
+    ) -> NetLoadError {
+        match failures.as_slice() {
+            [(_, e)] if e.kind() == IOErrorKind::TimedOut => NetLoadError::Timeout {
+                after: connect_timeout,
+                connecting: true,
+            },
+            _ => NetLoadError::ConnectFailed(failures),
+        }
+    }
+
+    /// Turns IO errors caused by a timeout into the proper variant
+    fn with_timeout(self, after: Duration, connecting: bool) -> NetLoadError {
+        match self {
+            // Unix reports an expired read/write timeout as WouldBlock
+            NetLoadError::IOError(e)
+                if e.kind() == IOErrorKind::TimedOut || e.kind() == IOErrorKind::WouldBlock =>
+            {
+                NetLoadError::Timeout { after, connecting }
+            }
+            e => e,
+        }
+    }
+}
+
+impl NetLoadError {
+    pub fn read_failed(path: &str, source: IOError) -> NetLoadError {
+        NetLoadError::ReadFailed {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn invalid_executable(path: &str, source: ExecutableError) -> NetLoadError {
+        NetLoadError::InvalidExecutable {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    fn invalid_package(path: &str, source: PackageError) -> NetLoadError {
+        NetLoadError::InvalidPackage {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn conversion_failed(path: &str, source: ConvertError) -> NetLoadError {
+        NetLoadError::ConversionFailed {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn invalid_stream(path: &str, source: ReceiveError) -> NetLoadError {
+        NetLoadError::InvalidStream {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub fn write_failed(path: &Path, source: IOError) -> NetLoadError {
+        NetLoadError::WriteFailed {
+            path: path.display().to_string(),
+            source,
+        }
+    }
+}
+
+impl From<DefaultAddressConfigError> for NetLoadError {
+    fn from(r: DefaultAddressConfigError) -> NetLoadError {
+        match r {
+            DefaultAddressConfigError::NoConfiguredDefault => NetLoadError::NoAddressPassed,
+            _ => NetLoadError::OtherConfigError(r),
+        }
+    }
+}
+
+impl From<IOError> for NetLoadError {
+    fn from(r: IOError) -> NetLoadError {
+        NetLoadError::IOError(r)
+    }
+}
+
+impl fmt::Display for NetLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetLoadError::NoAddressPassed => write!(
+                f,
+                "No address argument, {} not set and no default address configured, aborting.",
+                ENV_VAR_NAME
+            ),
+            NetLoadError::InvalidAddress(a) => write!(
+                f,
+                "Invalid address \"{}\", expected \"host\", \"host:port\" or \"[ipv6]:port\", aborting.",
+                a
+            ),
+            NetLoadError::CantResolveAddress => write!(f, "Cannot resolve passed address, aborting."),
+            NetLoadError::ArgsTooLong { length, max } => write!(
+                f,
+                "Arguments too long ({} bytes, maximum is {}), aborting.",
+                length, max
+            ),
+            NetLoadError::BinaryTooLong => write!(f, "Binary file too long, aborting."),
+            NetLoadError::ReadFailed { path, .. } => {
+                write!(f, "Could not read \"{}\", aborting.", path)
+            }
+            NetLoadError::InvalidExecutable { path, .. } => write!(
+                f,
+                "\"{}\" is not a valid Wii executable, aborting. (\"load --force\" sends it anyway)",
+                path
+            ),
+            NetLoadError::InvalidPackage { path, .. } => {
+                write!(f, "\"{}\" is not an app HBC can install, aborting.", path)
+            }
+            NetLoadError::ConversionFailed { path, .. } => {
+                write!(f, "Could not convert \"{}\" to a DOL, aborting.", path)
+            }
+            NetLoadError::InvalidStream { path, .. } => write!(
+                f,
+                "\"{}\" is not a complete upload, aborting. (\"replay --force\" sends it anyway)",
+                path
+            ),
+            NetLoadError::WriteFailed { path, .. } => {
+                write!(f, "Could not write \"{}\", aborting.", path)
+            }
+            NetLoadError::Timeout { after, connecting } => write!(
+                f,
+                "Timed out after {:.1}s while {}, aborting.",
+                after.as_secs_f64(),
+                if *connecting { "connecting" } else { "sending" }
+            ),
+            NetLoadError::ConnectFailed(failures) if failures.len() == 1 => {
+                write!(f, "Could not connect to {}, aborting.", failures[0].0)
+            }
+            NetLoadError::ConnectFailed(failures) => {
+                write!(f, "Could not connect to any resolved address, aborting.")?;
+                for (sock_addr, e) in failures {
+                    write!(f, "\n  {}: {}", sock_addr, e)?;
+                }
+                Ok(())
+            }
+            NetLoadError::IOError(_) => write!(f, "IO error, aborting."),
+            NetLoadError::OtherConfigError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for NetLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetLoadError::ReadFailed { source, .. } => Some(source),
+            NetLoadError::InvalidExecutable { source, .. } => Some(source),
+            NetLoadError::InvalidPackage { source, .. } => Some(source),
+            NetLoadError::ConversionFailed { source, .. } => Some(source),
+            NetLoadError::InvalidStream { source, .. } => Some(source),
+            NetLoadError::WriteFailed { source, .. } => Some(source),
+            NetLoadError::ConnectFailed(failures) if failures.len() == 1 => Some(&failures[0].1),
+            NetLoadError::IOError(e) => Some(e),
+            // Displayed as is, so skip straight to what caused it
+            NetLoadError::OtherConfigError(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl NetLoadError {
+    /// Short identifier, stable across versions
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetLoadError::NoAddressPassed => "no_address",
+            NetLoadError::InvalidAddress(_) => "invalid_address",
+            NetLoadError::CantResolveAddress => "cant_resolve",
+            NetLoadError::ConnectFailed(_) => "connect_failed",
+            NetLoadError::Timeout {
+                connecting: true, ..
+            } => "connect_timeout",
+            NetLoadError::Timeout {
+                connecting: false, ..
+            } => "send_timeout",
+            NetLoadError::ArgsTooLong { .. } => "args_too_long",
+            NetLoadError::BinaryTooLong => "binary_too_long",
+            NetLoadError::ReadFailed { .. } => "read_failed",
+            NetLoadError::InvalidExecutable { .. } => "invalid_executable",
+            NetLoadError::InvalidPackage { .. } => "invalid_package",
+            NetLoadError::ConversionFailed { .. } => "conversion_failed",
+            NetLoadError::InvalidStream { .. } => "invalid_stream",
+            NetLoadError::WriteFailed { .. } => "write_failed",
+            NetLoadError::IOError(_) => "io_error",
+            NetLoadError::OtherConfigError(e) => e.kind(),
+        }
+    }
+
+    /// Exit code of the riiload command for this error, stable across versions
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NetLoadError::IOError(_) => 1,
+            NetLoadError::NoAddressPassed => 10,
+            NetLoadError::InvalidAddress(_) => 11,
+            NetLoadError::CantResolveAddress => 12,
+            NetLoadError::ConnectFailed(_) => 13,
+            NetLoadError::Timeout {
+                connecting: true, ..
+            } => 14,
+            NetLoadError::Timeout {
+                connecting: false, ..
+            } => 15,
+            NetLoadError::ArgsTooLong { .. } => 20,
+            NetLoadError::BinaryTooLong => 21,
+            NetLoadError::InvalidExecutable { .. } => 22,
+            NetLoadError::InvalidPackage { .. } => 23,
+            NetLoadError::ReadFailed { .. } => 24,
+            NetLoadError::ConversionFailed { .. } => 25,
+            NetLoadError::InvalidStream { .. } => 26,
+            NetLoadError::WriteFailed { .. } => 27,
+            NetLoadError::OtherConfigError(e) => e.exit_code(),
+        }
+    }
+}
+
+const DEFAULT_COMPRESSION_LEVEL: u8 = 5; // From quick testing, "riiload bench" finds the best one for a given binary and link
+const AUTO_CANDIDATE_LEVELS: [u8; 3] = [1, 5, 9];
+const AUTO_MIN_SIZE: usize = 256 * 1024; // Below this, trying several levels costs more than it could ever save
+pub const DEFAULT_LINK_SPEED: u32 = 200; // KiB/s, rough Rx speed of a Wii over Wi-Fi
+const MAX_ARGS_LENGTH: usize = u16::MAX as usize; // Length is sent as a 16-bit field in the header
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const WRITE_CHUNK_SIZE: usize = 4096; // As written by wiiload-proto
+
+/// Builds the argument block HBC expects: argv[0] is the executable's file name, and every argument is NUL-terminated.
+fn build_args(name: &str, args: &[String]) -> String {
+    let mut block = String::new();
+    for arg in std::iter::once(name).chain(args.iter().map(String::as_str)) {
+        block.push_str(arg);
+        block.push('\0');
+    }
+    block
+}
+
+/// How the executable should be compressed before being sent
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compression {
+    Disabled,
+    /// Explicit level, or configured default if None
+    Level(Option<u8>),
+    /// Picks the level with the lowest estimated total time, based on the configured link speed.
+    /// Costs compressing at every candidate level, the picked one is then sent as is.
+    Auto,
+}
+
+/// Time it takes to send that many bytes at link_speed KiB/s
+pub fn transfer_time(length: usize, link_speed: u32) -> Duration {
+    Duration::from_secs_f64(length as f64 / (f64::from(link_speed) * 1024.0))
+}
+
+/// Compresses at a few levels and picks the one with the lowest compression + estimated transfer time.
+/// Returns the level along with the data compressed at it, None if sending uncompressed should be fastest.
+fn pick_compression_level(data: &[u8], link_speed: u32) -> Option<(u8, Vec<u8>)> {
+    let mut best = None;
+    let mut best_time = transfer_time(data.len(), link_speed);
+    for &level in AUTO_CANDIDATE_LEVELS.iter() {
+        let start = Instant::now();
+        let compressed = compress_to_vec_zlib(data, level);
+        let total = start.elapsed() + transfer_time(compressed.len(), link_speed);
+        if total < best_time {
+            best = Some((level, compressed));
+            best_time = total;
+        }
+    }
+
+    best
+}
+
+/// Removes the brackets around an IPv6 literal, as ToSocketAddrs does not want them
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, NetLoadError> {
+    match (unbracket(host), port).to_socket_addrs() {
+        Ok(i) => {
+            let sock_addrs: Vec<SocketAddr> = i.collect();
+            if sock_addrs.is_empty() {
+                return Err(NetLoadError::CantResolveAddress);
+            }
+            Ok(sock_addrs)
+        }
+        Err(_) => Err(NetLoadError::CantResolveAddress),
+    }
+}
+
+/// Connection attempt that failed, another one coming after the delay
+pub struct Retry<'a> {
+    /// Why each resolved address could not be connected to
+    pub failures: &'a [(SocketAddr, IOError)],
+    /// Starting from 1
+    pub attempt: u32,
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+/// Called with each failed attempt to connect, shared by every upload of a send_all
+pub type OnRetry = Arc<dyn Fn(&Retry) + Send + Sync>;
+
+/// Connects to the first resolved address that answers, trying again if asked to as the Wii may still be booting
+fn connect(
+    sock_addrs: &[SocketAddr],
+    connect_timeout: Duration,
+    retries: u32,
+    retry_delay: Duration,
+    on_retry: Option<&(dyn Fn(&Retry) + Send + Sync)>,
+) -> Result<TcpStream, NetLoadError> {
+    let mut attempt = 0;
+    loop {
+        let mut failures = Vec::new();
+        for sock_addr in sock_addrs {
+            match TcpStream::connect_timeout(sock_addr, connect_timeout) {
+                Ok(s) => return Ok(s),
+                Err(e) => failures.push((*sock_addr, e)),
+            }
+        }
+
+        if attempt >= retries {
+            return Err(NetLoadError::from_connect_failures(
+                failures,
+                connect_timeout,
+            ));
+        }
+        attempt += 1;
+        if let Some(on_retry) = on_retry {
+            on_retry(&Retry {
+                failures: &failures,
+                attempt,
+                retries,
+                delay: retry_delay,
+            });
+        }
+        sleep(retry_delay);
+    }
+}
+
+// ---------- Reading what to send ----------
+
+/// Path standing for stdin, for piping an executable in
+const STDIN_PATH: &str = "-";
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+fn read_input(path: &Path) -> Result<Vec<u8>, IOError> {
+    if is_stdin(path) {
+        let mut data = Vec::new();
+        stdin().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fsread(path)
+    }
+}
+
+/// Name of the file, or a made up one matching the format of the data if it came from stdin
+fn file_name(path: &Path, data: &[u8]) -> String {
+    if is_stdin(path) {
+        return match Executable::parse(data) {
+            Ok(Executable::Elf(_)) => "stdin.elf",
+            _ => "stdin.dol",
+        }
+        .to_string();
+    }
+    match path.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
+
+/// What to read and how to check it
+#[derive(Clone)]
+pub struct PayloadOptions {
+    /// File, app folder, or "-" for stdin
+    pub path: String,
+    /// File name or made up one if None
+    pub name: Option<String>,
+    /// Whether this is an app to install rather than an executable
+    pub install: bool,
+    /// Skips the checks
+    pub force: bool,
+    /// Converts an ELF executable to a DOL before sending it
+    pub to_dol: bool,
+}
+
+/// File as it will be sent, with the name HBC gets as argv[0]
+pub struct Payload {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// What the checks found, such as the executable's layout. None if forced.
+    pub description: Option<String>,
+}
+
+impl Payload {
+    /// Executable already in memory, checked unless forced
+    pub fn executable(name: &str, data: Vec<u8>, force: bool) -> Result<Payload, NetLoadError> {
+        let mut description = None;
+        if !force {
+            let executable =
+                Executable::parse(&data).map_err(|e| NetLoadError::invalid_executable(name, e))?;
+            description = Some(executable.to_string());
+        }
+        Ok(Payload {
+            name: name.to_string(),
+            data,
+            description,
+        })
+    }
+
+    /// Reads the executable, or app to install, checking it is something the Wii can use unless forced.
+    pub fn read(options: &PayloadOptions) -> Result<Payload, NetLoadError> {
+        let path = Path::new(&options.path);
+        let force = options.force;
+        let name = options.name.clone();
+
+        if !options.install {
+            let mut data =
+                read_input(path).map_err(|e| NetLoadError::read_failed(&options.path, e))?;
+            let mut default_name = file_name(path, &data);
+            if options.to_dol {
+                match elf_to_dol(&data) {
+                    Ok(dol) => {
+                        data = dol;
+                        default_name = Path::new(&default_name)
+                            .with_extension("dol")
+                            .to_string_lossy()
+                            .into_owned();
+                    }
+                    // Nothing to do
+                    Err(ConvertError::NotElf) => {}
+                    Err(e) => return Err(NetLoadError::conversion_failed(&options.path, e)),
+                }
+            }
+            // Make sure this won't just freeze the Wii
+            let mut description = None;
+            if !force {
+                let executable = Executable::parse(&data)
+                    .map_err(|e| NetLoadError::invalid_executable(&options.path, e))?;
+                description = Some(executable.to_string());
+            }
+            return Ok(Payload {
+                name: name.unwrap_or(default_name),
+                data,
+                description,
+            });
+        }
+
+        let (package, default_name) = if path.is_dir() {
+            let package = Package::from_directory(path)
+                .map_err(|e| NetLoadError::invalid_package(&options.path, e))?;
+            let name = format!("{}.zip", package.name);
+            (package, name)
+        } else {
+            let data = read_input(path).map_err(|e| NetLoadError::read_failed(&options.path, e))?;
+            let package = Package::from_zip(data)
+                .map_err(|e| NetLoadError::invalid_package(&options.path, e))?;
+            let name = match is_stdin(path) {
+                true => format!("{}.zip", package.name),
+                false => file_name(path, &package.data),
+            };
+            (package, name)
+        };
+        let name = name.unwrap_or(default_name);
+        let mut description = None;
+        if !force {
+            package
+                .check()
+                .map_err(|e| NetLoadError::invalid_package(&options.path, e))?;
+            description = Some(format!("\"{}\"", package.name));
+            if let Some(boot) = &package.boot {
+                let executable = Executable::parse(boot)
+                    .map_err(|e| NetLoadError::invalid_executable(&options.path, e))?;
+                description = Some(format!("\"{}\" with {}", package.name, executable));
+            }
+        }
+
+        Ok(Payload {
+            name,
+            data: package.data,
+            description,
+        })
+    }
+}
+
+// ---------- Sending ----------
+
+/// Where to send, and how. Anything left to None is taken from the configuration, as the riiload command does.
+#[derive(Clone)]
+pub struct Loader {
+    /// Taken from the target or environment or defaults if None
+    pub address: Option<String>,
+    /// Name of the configured target to use
+    pub target: Option<String>,
+    /// Overrides the port given with the address or target if set
+    pub port: Option<u16>,
+    pub compression: Compression,
+    /// Configured value or default if None
+    pub connect_timeout: Option<Duration>,
+    /// Configured value or default if None
+    pub io_timeout: Option<Duration>,
+    /// Number of times to try connecting again
+    pub retries: u32,
+    pub retry_delay: Duration,
+    /// Arguments passed to the executable after argv[0]
+    pub args: Vec<String>,
+    /// Draws a progress bar on stderr
+    pub progress: bool,
+    /// Called before waiting to connect again, for letting the user know
+    pub on_retry: Option<OnRetry>,
+    /// Used instead of the configuration file if set
+    pub config: Option<Config>,
+}
+
+impl Default for Loader {
+    fn default() -> Loader {
+        Loader {
+            address: None,
+            target: None,
+            port: None,
+            compression: Compression::Level(None),
+            connect_timeout: None,
+            io_timeout: None,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            args: Vec::new(),
+            progress: false,
+            on_retry: None,
+            config: None,
+        }
+    }
+}
+
+impl Loader {
+    /// Sends to this address, as "host", "host:port" or "[ipv6]:port"
+    pub fn new(address: &str) -> Loader {
+        Loader {
+            address: Some(address.to_string()),
+            ..Loader::default()
+        }
+    }
+
+    /// Sends to this configured target, with its settings
+    pub fn for_target(name: &str) -> Loader {
+        Loader {
+            target: Some(name.to_string()),
+            ..Loader::default()
+        }
+    }
+
+    /// One loader like this one per Wii to send to: each address, each target, then the members of each group,
+    /// or every configured target. A Wii given twice is only kept once, and this loader as is if none were given.
+    pub fn expand(
+        &self,
+        addresses: Vec<String>,
+        mut targets: Vec<String>,
+        groups: &[String],
+        all_targets: bool,
+    ) -> Result<Vec<Loader>, NetLoadError> {
+        if all_targets || !groups.is_empty() {
+            let config = match &self.config {
+                Some(c) => c.clone(),
+                None => get_config()?,
+            };
+            if all_targets {
+                if config.targets.is_empty() {
+                    return Err(NetLoadError::OtherConfigError(
+                        DefaultAddressConfigError::NoConfiguredDefault,
+                    ));
+                }
+                targets.extend(config.targets.keys().cloned());
+            }
+            for group in groups {
+                targets.extend(config.group(group)?.into_iter().map(str::to_string));
+            }
+        }
+
+        let mut loaders: Vec<Loader> = Vec::new();
+        let given = addresses
+            .into_iter()
+            .map(|a| Loader {
+                address: Some(a),
+                ..self.clone()
+            })
+            .chain(targets.into_iter().map(|t| Loader {
+                target: Some(t),
+                ..self.clone()
+            }));
+        for loader in given {
+            // Same Wii given twice, or through a group and by name
+            if !loaders
+                .iter()
+                .any(|l| l.address == loader.address && l.target == loader.target)
+            {
+                loaders.push(loader);
+            }
+        }
+        if loaders.is_empty() {
+            loaders.push(self.clone());
+        }
+        Ok(loaders)
+    }
+
+    /// Target or address as given, for telling Wiis apart before their destination is known
+    pub fn name(&self) -> Option<&str> {
+        self.target.as_deref().or(self.address.as_deref())
+    }
+
+    /// Works out where to connect and with which settings, looking at the configuration if needed
+    pub fn destination(&self) -> Result<Destination, NetLoadError> {
+        // An address given directly does not depend on the configuration, which only fills in settings then
+        let (config, config_error) = match (&self.config, &self.address) {
+            (Some(c), _) => (c.clone(), None),
+            (None, Some(_)) => match get_settings() {
+                Ok(c) => (c, None),
+                Err(e) => (Config::default(), Some(e)),
+            },
+            (None, None) => (get_config()?, None),
+        };
+        let to_connect = maybe_get_address(&config, self.address.clone(), self.target.clone())?;
+        let port = self.port.or(to_connect.port).unwrap_or(TCP_PORT);
+        let sock_addrs = resolve(&to_connect.address, port)?;
+
+        // Target's level, then configured default, then built-in default
+        let default_level = match to_connect.compression_level {
+            Some(l) => l,
+            None => config
+                .compression_level()?
+                .unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+        };
+        let connect_timeout = match self.connect_timeout.or(to_connect.connect_timeout) {
+            Some(t) => t,
+            None => config.connect_timeout()?.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+        };
+        let io_timeout = match self.io_timeout.or(to_connect.io_timeout) {
+            Some(t) => t,
+            None => config.io_timeout()?.unwrap_or(DEFAULT_IO_TIMEOUT),
+        };
+
+        Ok(Destination {
+            address: to_connect.address,
+            port,
+            source: to_connect.source,
+            sock_addrs,
+            default_level,
+            link_speed: config.link_speed()?.unwrap_or(DEFAULT_LINK_SPEED),
+            connect_timeout,
+            io_timeout,
+            config_error,
+            ignored_environment: to_connect.ignored_environment,
+            loader: self.clone(),
+        })
+    }
+
+    /// Finds the destination and sends the payload there
+    pub fn load(&self, payload: &Payload) -> Result<Transfer, NetLoadError> {
+        self.destination()?.send(payload)
+    }
+}
+
+/// Wii to send to, with every setting worked out
+pub struct Destination {
+    pub address: String,
+    pub port: u16,
+    /// Where the address was found
+    pub source: AddressSource,
+    pub sock_addrs: Vec<SocketAddr>,
+    /// Used when the compression level was not given, from the target, the configuration or built-in default
+    pub default_level: u8,
+    /// In KiB/s, for picking a compression level automatically
+    pub link_speed: u32,
+    pub connect_timeout: Duration,
+    pub io_timeout: Duration,
+    /// Why the configuration could not be read, built-in defaults were used instead
+    pub config_error: Option<DefaultAddressConfigError>,
+    /// WIILOAD value that is not a network address, skipped for the default target
+    pub ignored_environment: Option<String>,
+    loader: Loader,
+}
+
+/// Upload ready to be transmitted
+pub struct Encoded {
+    /// Level the binary was compressed at, None if uncompressed
+    pub level: Option<u8>,
+    pub header: Header,
+    /// Everything that goes through the socket: header, binary, then arguments
+    pub stream: Vec<u8>,
+}
+
+impl Encoded {
+    fn new(level: Option<u8>, stream: Vec<u8>) -> Encoded {
+        let header = Header::parse(&stream).expect("wiiload-proto always writes a header");
+        Encoded {
+            level,
+            header,
+            stream,
+        }
+    }
+}
+
+impl Destination {
+    /// Turns the requested compression into the level to pass to wiiload-proto, None meaning uncompressed
+    fn requested_level(&self) -> Option<u8> {
+        match self.loader.compression {
+            Compression::Disabled => None,
+            Compression::Level(l) => Some(l.unwrap_or(self.default_level)),
+            // Small enough that picking is not worth it
+            Compression::Auto => Some(self.default_level),
+        }
+    }
+
+    /// Key telling whether two destinations would compress and send a payload the same way
+    fn encoding(&self) -> (Compression, u8, u32, Vec<String>) {
+        (
+            self.loader.compression,
+            self.default_level,
+            self.link_speed,
+            self.loader.args.clone(),
+        )
+    }
+
+    /// Compresses the payload as requested, or at the level that should be fastest with --auto
+    pub fn encode(&self, payload: &Payload) -> Result<Encoded, NetLoadError> {
+        if self.loader.compression != Compression::Auto || payload.data.len() < AUTO_MIN_SIZE {
+            let level = self.requested_level();
+            let stream = self.encode_at(payload, level)?;
+            return Ok(Encoded::new(level, stream));
+        }
+
+        // Check arguments before compressing
+        let args = self.args(payload)?;
+        let encoded = match pick_compression_level(&payload.data, self.link_speed) {
+            Some((level, compressed)) => {
+                // Already compressed, wiiload-proto would not know the raw size to announce
+                let size = |data: &[u8]| {
+                    u32::try_from(data.len()).map_err(|_| NetLoadError::BinaryTooLong)
+                };
+                let header = Header::new(
+                    args.len() as u16, // Checked against MAX_ARGS_LENGTH
+                    size(&compressed)?,
+                    size(&payload.data)?,
+                );
+                let mut stream = Vec::with_capacity(header.upload_length() as usize);
+                header.write(&mut stream)?;
+                stream.extend_from_slice(&compressed);
+                stream.extend_from_slice(args.as_bytes());
+                Encoded {
+                    level: Some(level),
+                    header,
+                    stream,
+                }
+            }
+            None => Encoded::new(None, write_stream(args, &payload.data, None)?),
+        };
+        Ok(encoded)
+    }
+
+    /// Builds everything that goes through the socket at the given level, None meaning uncompressed
+    pub fn encode_at(&self, payload: &Payload, level: Option<u8>) -> Result<Vec<u8>, NetLoadError> {
+        // Check arguments before compressing
+        let args = self.args(payload)?;
+        write_stream(args, &payload.data, level)
+    }
+
+    fn args(&self, payload: &Payload) -> Result<String, NetLoadError> {
+        let args = build_args(&payload.name, &self.loader.args);
+        if args.len() > MAX_ARGS_LENGTH {
+            return Err(NetLoadError::ArgsTooLong {
+                length: args.len(),
+                max: MAX_ARGS_LENGTH,
+            });
+        }
+        Ok(args)
+    }
+
+    /// Rough time sending that many bytes takes, based on the configured link speed
+    pub fn estimated_transfer_time(&self, length: usize) -> Duration {
+        transfer_time(length, self.link_speed)
+    }
+
+    /// Connects to the Wii and writes an upload, as built by encode or captured earlier
+    pub fn transmit(&self, stream: &[u8]) -> Result<Transfer, NetLoadError> {
+        // Connect to wii
+        let mut socket = connect(
+            &self.sock_addrs,
+            self.connect_timeout,
+            self.loader.retries,
+            self.loader.retry_delay,
+            self.loader.on_retry.as_deref(),
+        )?;
+        socket.set_read_timeout(Some(self.io_timeout))?;
+        socket.set_write_timeout(Some(self.io_timeout))?;
+
+        // Actually send, in small enough pieces for the progress bar to move
+        let mut writer = ProgressWriter::new(&mut socket, self.loader.progress);
+        let result = stream
+            .chunks(WRITE_CHUNK_SIZE)
+            .try_for_each(|c| writer.write_all(c));
+        let transfer = writer.finish();
+        result.map_err(|e| NetLoadError::from(e).with_timeout(self.io_timeout, false))?;
+
+        Ok(transfer.unwrap_or_default())
+    }
+
+    pub fn send(&self, payload: &Payload) -> Result<Transfer, NetLoadError> {
+        self.transmit(&self.encode(payload)?.stream)
+    }
+}
+
+/// Header, binary compressed at level by wiiload-proto, then arguments
+fn write_stream(args: String, data: &[u8], level: Option<u8>) -> Result<Vec<u8>, NetLoadError> {
+    let args_length = args.len();
+    let mut stream = Vec::new();
+    net_send(&mut stream, data, args, level)
+        .map_err(|e| NetLoadError::from_send_fail(e, args_length))?;
+    Ok(stream)
+}
+
+/// What happened to the upload to one of several Wiis
+pub struct Delivery {
+    /// "host:port" once resolved, otherwise the target or address as given
+    pub target: Option<String>,
+    pub result: Result<Transfer, NetLoadError>,
+}
+
+/// Works out the destination of every loader, then sends the payload to all of them at once with send_all.
+/// on_resolved is called with each destination before anything is sent. Results are in the same order as loaders.
+pub fn load_all<F: FnMut(&Destination)>(
+    loaders: &[Loader],
+    payload: &Payload,
+    mut on_resolved: F,
+) -> Vec<Delivery> {
+    let mut deliveries = Vec::new();
+    let mut destinations = Vec::new();
+    for loader in loaders {
+        let delivery = match loader.destination() {
+            Ok(destination) => {
+                on_resolved(&destination);
+                let target = join_host_port(&destination.address, destination.port);
+                destinations.push(destination);
+                Delivery {
+                    target: Some(target),
+                    result: Ok(Transfer::default()), // Replaced once sent
+                }
+            }
+            Err(e) => Delivery {
+                target: loader.name().map(str::to_string),
+                result: Err(e),
+            },
+        };
+        deliveries.push(delivery);
+    }
+
+    let transfers = send_all(destinations, payload);
+    let resolved = deliveries.iter_mut().filter(|d| d.result.is_ok());
+    for (delivery, transfer) in resolved.zip(transfers) {
+        delivery.result = transfer;
+    }
+    deliveries
+}
+
+/// Sends the same payload to several Wiis at once, each from its own thread.
+/// Destinations sharing the same compression settings and arguments reuse the same compressed data.
+/// No progress bar is drawn. Results are in the same order as destinations.
+pub fn send_all(
+    destinations: Vec<Destination>,
+    payload: &Payload,
+) -> Vec<Result<Transfer, NetLoadError>> {
+    let mut encoded: Vec<(_, Arc<Encoded>)> = Vec::new();
+
+    let uploads: Vec<_> = destinations
+        .into_iter()
+        .map(|mut destination| {
+            destination.loader.progress = false;
+            let key = destination.encoding();
+            let upload = match encoded.iter().find(|(k, _)| *k == key) {
+                Some((_, e)) => Arc::clone(e),
+                None => {
+                    let upload = Arc::new(destination.encode(payload)?);
+                    encoded.push((key, Arc::clone(&upload)));
+                    upload
+                }
+            };
+            Ok(thread::spawn(move || destination.transmit(&upload.stream)))
+        })
+        .collect();
+
+    uploads
+        .into_iter()
+        .map(|u| u.and_then(|h| h.join().expect("upload thread panicked")))
+        .collect()
+}
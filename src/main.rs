@@ -1,298 +1,1741 @@
-use dirs::config_dir;
-use structopt::StructOpt;
+use riiload::bench::configured_link_speed;
+use riiload::bench::fastest;
+use riiload::bench::measure_link_speed;
+use riiload::bench::time_levels;
+use riiload::bench::LevelTiming;
+use riiload::config::get_config;
+use riiload::config::get_config_path;
+use riiload::config::get_default_address;
+use riiload::config::remove_config_files;
+use riiload::config::set_config;
+use riiload::config::set_default_address;
+use riiload::config::DefaultAddressConfigError;
+use riiload::discover::discover;
+use riiload::discover::Responder;
+use riiload::executable::elf_to_dol;
+use riiload::executable::Dol;
+use riiload::executable::Elf;
+use riiload::executable::Executable;
+use riiload::executable::Layout;
+use riiload::is_stdin;
+use riiload::join_host_port;
+use riiload::load_all;
+use riiload::parse_address;
+use riiload::parse_compression_level;
+use riiload::parse_link_speed;
+use riiload::parse_seconds;
+use riiload::progress::format_size;
+use riiload::progress::Transfer;
+use riiload::receive::parse_args;
+use riiload::receive::read_upload;
+use riiload::receive::Header;
+use riiload::receive::Receiver;
+use riiload::receive::Upload;
+use riiload::Compression;
+use riiload::Destination;
+use riiload::Encoded;
+use riiload::Loader;
+use riiload::NetLoadError;
+use riiload::Payload;
+use riiload::PayloadOptions;
+use riiload::Retry;
+use riiload::Target;
+use riiload::ENV_VAR_NAME;
+use riiload::TCP_PORT;
 
-use wiiload_proto::net_send;
-use wiiload_proto::WiiLoadFail;
+use chrono::Local;
+use serde::Serialize;
+use structopt::clap::AppSettings;
+use structopt::clap::Error;
+use structopt::clap::ErrorKind;
+use structopt::StructOpt;
 
+use std::env::var_os;
+use std::error::Error as StdError;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::fs::metadata;
 use std::fs::read as fsread;
-use std::fs::read_to_string;
-use std::fs::remove_file;
-use std::fs::File;
+use std::fs::write;
+use std::io::stderr;
+use std::io::stdin;
 use std::io::Error as IOError;
 use std::io::ErrorKind as IOErrorKind;
 use std::io::Write;
+use std::iter::successors;
 use std::net::SocketAddr;
-use std::net::TcpStream;
-use std::net::ToSocketAddrs;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 // ---------- Command Line Opts ----------
 
 // TODO: Disable per-subcommand version info
-// TODO: Arguments
-// TODO: Allow changing compression level
+
+/// Dumps errors as they are represented internally along with --verbose when set
+const DEBUG_VAR_NAME: &str = "RIILOAD_DEBUG";
+
+/// Shown at the end of the help, so that scripts can tell failures apart
+const EXIT_CODES: &str = "EXIT CODES:
+    0     Success
+    1     Other IO error
+    2     Invalid command line
+    10    No address given or configured
+    11    Invalid address
+    12    Address could not be resolved
+    13    Connection failed
+    14    Timed out while connecting
+    15    Timed out while sending
+    20    Arguments too long
+    21    Binary too long
+    22    Not a valid executable
+    23    Not a valid app package
+    24    Executable or app could not be read
+    25    ELF could not be converted to a DOL
+    26    Captured stream is not a complete upload
+    27    Output file could not be written
+    30    No folder for storing configuration
+    31    Not configured
+    32    Configuration file could not be accessed
+    33    Invalid WIILOAD environment variable
+    34    Invalid value in configuration
+    35    Malformed configuration file
+    36    Unknown target
+    37    Unknown group
+
+When sending to several Wiis, the code is the one of the first Wii that failed.";
+
+#[derive(StructOpt)]
+#[structopt(after_help = EXIT_CODES)]
+struct Opt {
+    /// Print the result, or error, as a JSON object on stdout instead of text. Sending to several Wiis prints one line for each.
+    #[structopt(long, global = true)]
+    json: bool,
+    /// Print extra information, such as where the address used came from, and details about errors. Setting RIILOAD_DEBUG also dumps errors as represented internally.
+    #[structopt(short, long, global = true)]
+    verbose: bool,
+    #[structopt(subcommand)]
+    command: Commands,
+}
 
 #[derive(StructOpt)]
 enum Commands {
     /// Send an executable to a Wii running the HBC and connected to a network reachable from this computer.
     Load(LoadCommand),
 
+    /// Send an executable followed by the arguments to pass it, for use as a Cargo runner with runner = "riiload run".
+    Run(RunCommand),
+
+    /// Send a stream captured with "load --dump-stream" again, byte for byte.
+    Replay(ReplayCommand),
+
     /// Configure defaults to use for omitting arguments while using "load".
     Config(ConfigCommand),
+
+    /// Look for Wiis running the HBC on the local networks of this computer.
+    Discover(DiscoverCommand),
+
+    /// Print the layout of an ELF/DOL executable once loaded in memory.
+    Inspect(InspectCommand),
+
+    /// Convert an ELF executable to a DOL.
+    Convert(ConvertCommand),
+
+    /// Time compression at every level, and optionally measure the link to a Wii, to find the fastest level.
+    Bench(BenchCommand),
+
+    /// Act like the HBC, receiving uploads for testing without a Wii.
+    Receive(ReceiveCommand),
 }
 
 #[derive(StructOpt)]
 struct LoadCommand {
-    /// ELF/DOL executable file to send to the Wii.
+    /// ELF/DOL executable file to send to the Wii, or app folder/ZIP file with --install. Use "-" to read it from stdin.
     executable: String,
-    /// Address of the target Wii. If not provided, the program will attempt to read the default from the configuration file.
-    address: Option<String>,
+    /// Addresses of the target Wiis, as "host", "host:port" or "[ipv6]:port". If neither this nor a target is provided, the program will attempt to read it from the WIILOAD environment variable ("tcp:host[:port]"), then use the default target or address from the configuration.
+    addresses: Vec<String>,
+    /// Name of a configured target to send to, using its address and settings. Can be repeated, and combined with addresses to send to several Wiis at once.
+    #[structopt(short, long = "target", number_of_values = 1)]
+    targets: Vec<String>,
+    /// Sends to every configured target belonging to this group. Can be repeated.
+    #[structopt(short, long = "group", number_of_values = 1)]
+    groups: Vec<String>,
+    /// Sends to every configured target.
+    #[structopt(long)]
+    all_targets: bool,
+    /// TCP port to connect to, overriding any port given with the address. Defaults to 4299, the port used by the HBC.
+    #[structopt(short, long)]
+    port: Option<u16>,
     /// Sends the binary uncompressed. Compression is enabled by default as the bottleneck generally is the Wii's Rx speed.
     #[structopt(short, long)]
     no_compression: bool,
+    /// Compression level, from 0 (fastest) to 9 (smallest). If not provided, the configured default is used, or 5 if there is none.
+    #[structopt(short, long, conflicts_with = "no-compression", parse(try_from_str = parse_compression_level))]
+    level: Option<u8>,
+    /// Compresses at a few levels and picks the one with the lowest estimated total time, based on the configured link speed.
+    #[structopt(short, long, conflicts_with_all = &["level", "no-compression"])]
+    auto: bool,
+    /// Arguments passed to the executable, placed after "--". The executable's file name is always sent as argv[0].
+    #[structopt(last = true)]
+    args: Vec<String>,
+    /// Sends the file even if it does not look like an executable the Wii can run.
+    #[structopt(short, long)]
+    force: bool,
+    /// Installs an app to the SD card instead of running it. Takes an app folder such as "apps/myapp/", zipped on the fly, or a ZIP file containing one.
+    #[structopt(short, long)]
+    install: bool,
+    /// Converts an ELF executable to a DOL before sending it, a DOL is sent as is.
+    #[structopt(long, conflicts_with = "install")]
+    to_dol: bool,
+    /// Writes every byte that goes through the socket, header and arguments included, to this file. It can be sent again with "replay".
+    #[structopt(long)]
+    dump_stream: Option<PathBuf>,
+    /// Does everything but connecting to the Wii, then prints where it would have connected and what it would have sent.
+    #[structopt(long, conflicts_with = "watch")]
+    dry_run: bool,
+    /// Keeps running and sends the executable again every time it changes, waiting for it to be completely written first.
+    #[structopt(short, long, conflicts_with = "install")]
+    watch: bool,
+    /// Name sent as argv[0] and shown by HBC. Defaults to the file name, or "stdin.dol"/"stdin.elf" when reading from stdin.
+    #[structopt(long)]
+    name: Option<String>,
+    /// Do not show the progress bar and transfer summary.
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Seconds to wait for the connection to be established. Defaults to the configured value, or 5.
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    connect_timeout: Option<Duration>,
+    /// Seconds to wait for the Wii to accept data before giving up. Defaults to the configured value, or 30.
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    io_timeout: Option<Duration>,
+    /// Number of times to try connecting again if it fails, for instance while the Wii is still booting into the HBC.
+    #[structopt(short, long, default_value = "0")]
+    retries: u32,
+    /// Seconds to wait between two connection attempts.
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_seconds))]
+    retry_delay: Duration,
+}
+
+#[derive(StructOpt)]
+struct DiscoverCommand {
+    /// TCP port to probe.
+    #[structopt(short, long, default_value = "4299")]
+    port: u16,
+    /// Seconds to wait for each host to accept the connection.
+    #[structopt(short, long, default_value = "0.5", parse(try_from_str = parse_seconds))]
+    timeout: Duration,
+    /// Offer to save one of the found Wiis as the default address.
+    #[structopt(short, long)]
+    save: bool,
+}
+
+#[derive(StructOpt)]
+struct InspectCommand {
+    /// ELF/DOL executable file to inspect.
+    executable: String,
+}
+
+#[derive(StructOpt)]
+struct BenchCommand {
+    /// ELF/DOL executable file to compress, and send when measuring the link.
+    executable: String,
+    /// Address of a Wii, or of "riiload receive", to measure the speed of the link with. The executable is sent uncompressed and run by the Wii. Without this nor a target, the configured link speed is used.
+    address: Option<String>,
+    /// Name of a configured target to measure the link with.
+    #[structopt(short, long, conflicts_with = "address")]
+    target: Option<String>,
+    /// TCP port to connect to, overriding any port given with the address.
+    #[structopt(short, long)]
+    port: Option<u16>,
+    /// Uses the file even if it does not look like an executable the Wii can run.
+    #[structopt(short, long)]
+    force: bool,
+    /// Saves the fastest level as the default one, or as the target's with --target, and the measured link speed if any.
+    #[structopt(short, long)]
+    save: bool,
+}
+
+#[derive(StructOpt)]
+struct ConvertCommand {
+    /// ELF executable file to convert.
+    executable: String,
+    /// DOL file to write. Defaults to the ELF's path with a ".dol" extension.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+#[structopt(setting = AppSettings::TrailingVarArg)]
+struct RunCommand {
+    /// ELF/DOL executable file to send to the Wii followed by the arguments to pass it, as given by Cargo. Options must come before the executable, its file name is always sent as argv[0].
+    #[structopt(required = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+    /// Name of a configured target to send to. If not provided, the WIILOAD environment variable is used, then the default target or address from the configuration.
+    #[structopt(short, long)]
+    target: Option<String>,
+    /// Do not show the progress bar and transfer summary.
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Number of times to try connecting again if it fails, for instance while the Wii is still booting into the HBC.
+    #[structopt(short, long, default_value = "0")]
+    retries: u32,
+    /// Converts an ELF executable to a DOL before sending it, a DOL is sent as is.
+    #[structopt(long)]
+    to_dol: bool,
+}
+
+#[derive(StructOpt)]
+struct ReplayCommand {
+    /// File written by "load --dump-stream".
+    stream: String,
+    /// Address of the target Wii, as "host", "host:port" or "[ipv6]:port". If neither this nor a target is provided, the WIILOAD environment variable is used, then the default target or address from the configuration.
+    address: Option<String>,
+    /// Name of a configured target to send to.
+    #[structopt(short, long, conflicts_with = "address")]
+    target: Option<String>,
+    /// TCP port to connect to, overriding any port given with the address.
+    #[structopt(short, long)]
+    port: Option<u16>,
+    /// Sends the file even if it does not look like a complete upload.
+    #[structopt(short, long)]
+    force: bool,
+    /// Do not show the progress bar and transfer summary.
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Number of times to try connecting again if it fails.
+    #[structopt(short, long, default_value = "0")]
+    retries: u32,
+}
+
+#[derive(StructOpt)]
+struct ReceiveCommand {
+    /// TCP port to listen on.
+    #[structopt(short, long, default_value = "4299")]
+    port: u16,
+    /// Address to listen on.
+    #[structopt(short, long, default_value = "0.0.0.0")]
+    bind: String,
+    /// Folder to write received files to, along with a ".args" file listing their arguments one per line.
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+    /// Exit after the first upload, with an error if it was invalid.
+    #[structopt(long)]
+    once: bool,
 }
 
 #[derive(StructOpt)]
 enum ConfigCommand {
-    /// Address to use by default for connecting to the Wii.
+    /// Address to use by default for connecting to the Wii. This is the address of the default target if there is one.
     DefaultAddress(ConfigDefaultAddressCommand),
 
+    /// Named Wiis, each with their own address and settings.
+    Target(ConfigTargetCommand),
+
+    /// Compression level to use by default.
+    CompressionLevel(ConfigCompressionLevelCommand),
+
+    /// Estimated speed of the link to the Wii, used for picking a compression level with "load --auto".
+    LinkSpeed(ConfigLinkSpeedCommand),
+
+    /// Time to wait for the connection to be established.
+    ConnectTimeout(ConfigTimeoutCommand),
+
+    /// Time to wait for the Wii to accept data while sending.
+    IoTimeout(ConfigTimeoutCommand),
+
     /// Config-file related functions.
     File(ConfigFileCommand),
 }
 
 #[derive(StructOpt)]
 enum ConfigDefaultAddressCommand {
-    /// Set the address.
-    Set { address: String },
+    /// Set the address, as "host", "host:port" or "[ipv6]:port".
+    Set {
+        #[structopt(parse(try_from_str = parse_address))]
+        address: String,
+    },
     /// Print the address.
     Get,
 }
 
+#[derive(StructOpt)]
+enum ConfigTargetCommand {
+    /// Add a target, replacing any existing one with the same name.
+    Add {
+        name: String,
+        /// Address, as "host", "host:port" or "[ipv6]:port".
+        #[structopt(parse(try_from_str = parse_address))]
+        address: String,
+        /// TCP port, overriding any port given with the address.
+        #[structopt(short, long)]
+        port: Option<u16>,
+        /// Compression level, from 0 (fastest) to 9 (smallest).
+        #[structopt(short, long, parse(try_from_str = parse_compression_level))]
+        level: Option<u8>,
+        /// Seconds to wait for the connection to be established.
+        #[structopt(long, parse(try_from_str = parse_seconds))]
+        connect_timeout: Option<Duration>,
+        /// Seconds to wait for the Wii to accept data while sending.
+        #[structopt(long, parse(try_from_str = parse_seconds))]
+        io_timeout: Option<Duration>,
+        /// Group to put the target in, for sending to all of its targets with "load --group". Can be repeated.
+        #[structopt(short, long = "group", number_of_values = 1)]
+        groups: Vec<String>,
+    },
+    /// List the targets, the default one being marked with "*".
+    List,
+    /// Remove a target.
+    Remove { name: String },
+    /// Use a target when "load" is not given an address.
+    SetDefault { name: String },
+}
+
+#[derive(StructOpt)]
+enum ConfigCompressionLevelCommand {
+    /// Set the level, from 0 (fastest) to 9 (smallest).
+    Set {
+        #[structopt(parse(try_from_str = parse_compression_level))]
+        level: u8,
+    },
+    /// Print the level.
+    Get,
+}
+
+#[derive(StructOpt)]
+enum ConfigLinkSpeedCommand {
+    /// Set the speed, in KiB/s.
+    Set {
+        #[structopt(parse(try_from_str = parse_link_speed))]
+        speed: u32,
+    },
+    /// Print the speed, in KiB/s.
+    Get,
+}
+
+#[derive(StructOpt)]
+enum ConfigTimeoutCommand {
+    /// Set the timeout, in seconds.
+    Set {
+        #[structopt(parse(try_from_str = parse_seconds))]
+        timeout: Duration,
+    },
+    /// Print the timeout, in seconds.
+    Get,
+}
+
 #[derive(StructOpt)]
 enum ConfigFileCommand {
-    /// Completely remove the configuration file.
+    /// Completely remove the configuration files.
     Delete,
     /// Print the configuration file path.
     PrintPath,
 }
 
-// ---------- Config file handling / getting address ----------
+// ---------- Reporting ----------
 
-const FILE_NAME: &str = "riiload_config";
-
-enum DefaultAddressConfigError {
-    /// "dirs" crate could not find a suitable storage location
-    NoSuitableFolder,
-    /// No configuration found
-    NoConfiguredDefault,
-    /// Could not read/write to file properly
-    FileAccess(IOError),
+/// Error as printed with --json
+#[derive(Serialize)]
+struct ErrorReport {
+    /// Identifier, one per error variant
+    kind: &'static str,
+    /// What the process exits with
+    code: i32,
+    message: String,
+    /// Messages of the errors that led to this one, outermost first
+    causes: Vec<String>,
 }
 
-impl From<IOError> for DefaultAddressConfigError {
-    fn from(r: IOError) -> DefaultAddressConfigError {
-        DefaultAddressConfigError::FileAccess(r)
+impl ErrorReport {
+    fn new(e: &NetLoadError) -> ErrorReport {
+        ErrorReport {
+            kind: e.kind(),
+            code: e.exit_code(),
+            message: e.to_string(),
+            causes: causes(e).map(|c| c.to_string()).collect(),
+        }
     }
 }
 
-impl DefaultAddressConfigError {
-    fn print_problem_and_exit(&self) {
-        eprint!("error: ");
-        match self {
-            DefaultAddressConfigError::NoSuitableFolder => {
-                eprintln!("Could not find a folder for storing configuration, aborting.")
-            }
-            DefaultAddressConfigError::NoConfiguredDefault => {
-                eprintln!("No configuration file found, aborting.")
-            }
-            DefaultAddressConfigError::FileAccess(e) => {
-                eprintln!("Problem while accessing file ({:?})", e.kind())
+/// Result of a command as printed with --json, only errors are filled in for commands other than "load"
+#[derive(Serialize, Default)]
+struct LoadReport {
+    /// "ok" or "error"
+    status: &'static str,
+    /// Name sent as argv[0]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    /// Address and port the executable was sent to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// Everything that went through the socket, header and arguments included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_sent: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compressed_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_size: Option<u64>,
+    /// Seconds spent sending
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run: Option<DryRunReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorReport>,
+    /// As recorded, for printing a summary
+    #[serde(skip)]
+    transfer: Option<Transfer>,
+}
+
+impl LoadReport {
+    fn record(&mut self, transfer: &Transfer) {
+        self.bytes_sent = Some(transfer.sent);
+        self.compressed_size = Some(transfer.compressed);
+        self.raw_size = Some(transfer.raw);
+        self.duration = Some(transfer.duration.as_secs_f64());
+        self.transfer = Some(transfer.clone());
+    }
+
+    /// Fills in status and error, then prints the report as a single line
+    fn print(mut self, error: Option<&NetLoadError>) {
+        match error {
+            None => self.status = "ok",
+            Some(e) => {
+                self.status = "error";
+                self.error = Some(ErrorReport::new(e));
             }
         }
-        exit(1)
+        println!("{}", serde_json::to_string(&self).unwrap());
     }
 }
 
-fn get_config_path() -> Result<PathBuf, DefaultAddressConfigError> {
-    let mut config = match config_dir() {
-        Some(c) => c,
-        _ => return Err(DefaultAddressConfigError::NoSuitableFolder),
+/// Successful outcome of a command other than sending, as printed with --json
+#[derive(Serialize)]
+struct StatusReport<T> {
+    /// Always "ok", failures are printed as a LoadReport
+    status: &'static str,
+    #[serde(flatten)]
+    details: T,
+}
+
+/// Prints what a command did as a single line, along with its status
+fn print_ok<T: Serialize>(details: T) {
+    let report = StatusReport {
+        status: "ok",
+        details,
     };
+    println!("{}", serde_json::to_string(&report).unwrap());
+}
 
-    config.push(FILE_NAME);
+/// Header fields and arguments, as the Wii would read them
+#[derive(Serialize)]
+struct HeaderReport {
+    #[serde(flatten)]
+    fields: Header,
+    /// argv, starting with the file name
+    args: Vec<String>,
+}
 
-    Ok(config)
+/// What would have been sent, worked out without connecting
+#[derive(Serialize)]
+struct DryRunReport {
+    /// Where the address came from
+    address_source: String,
+    resolved: Vec<SocketAddr>,
+    /// Description of the executable, None for an app package or a forced file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    executable: Option<String>,
+    /// None if sent uncompressed
+    level: Option<u8>,
+    header: HeaderReport,
+    args_length: usize,
+    /// Size of the file itself
+    file_size: usize,
+    /// Everything that would go through the socket
+    stream_size: usize,
+    /// In KiB/s
+    link_speed: u32,
+    /// In seconds
+    estimated_time: f64,
 }
 
-fn get_default_address() -> Result<String, DefaultAddressConfigError> {
-    // TODO: Map error ?
-    match read_to_string(get_config_path()?) {
-        Ok(s) => Ok(s),
-        Err(e) => match e.kind() {
-            IOErrorKind::NotFound => Err(DefaultAddressConfigError::NoConfiguredDefault),
-            _ => Err(DefaultAddressConfigError::FileAccess(e)),
-        },
+impl DryRunReport {
+    fn new(destination: &Destination, payload: &Payload, encoded: &Encoded) -> DryRunReport {
+        let stream = &encoded.stream;
+        // Arguments come last
+        let args_length = encoded.header.args_length as usize;
+        let args = parse_args(&stream[stream.len() - args_length..]).unwrap_or_default();
+        DryRunReport {
+            address_source: destination.source.to_string(),
+            resolved: destination.sock_addrs.clone(),
+            executable: Executable::parse(&payload.data).ok().map(|e| e.to_string()),
+            level: encoded.level,
+            header: HeaderReport {
+                fields: encoded.header,
+                args,
+            },
+            args_length,
+            file_size: payload.data.len(),
+            stream_size: stream.len(),
+            link_speed: destination.link_speed,
+            estimated_time: destination
+                .estimated_transfer_time(stream.len())
+                .as_secs_f64(),
+        }
     }
+
+    fn print(&self, target: &str) {
+        println!("Dry run, nothing was sent");
+        println!("Target: {}, from {}", target, self.address_source);
+        for sock_addr in &self.resolved {
+            println!("Resolved to: {}", sock_addr);
+        }
+        if let Some(e) = &self.executable {
+            println!("Executable: {}", e);
+        }
+        match self.level {
+            Some(l) => println!("Compression: level {}", l),
+            None => println!("Compression: none"),
+        }
+        println!(
+            "Header: version {}.{}, arguments length {}, compressed size {}, raw size {}",
+            self.header.fields.major,
+            self.header.fields.minor,
+            self.args_length,
+            self.header.fields.compressed_size,
+            self.header.fields.raw_size
+        );
+        println!("Arguments: {:?}", self.header.args);
+        println!(
+            "Sizes: {} raw, {} sent, {} in total with header and arguments",
+            format_size(self.file_size as u64),
+            format_size(self.header.fields.compressed_size as u64),
+            format_size(self.stream_size as u64)
+        );
+        println!(
+            "Estimated transfer time: {:.1}s at {} KiB/s",
+            self.estimated_time, self.link_speed
+        );
+    }
+}
+
+/// Every error behind this one, outermost first
+fn causes(e: &NetLoadError) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+    successors(e.source(), |&c| c.source())
 }
 
-/// Maybe gets the default address if option is not present
-fn maybe_get_address(address: Option<String>) -> Result<String, DefaultAddressConfigError> {
-    match address {
-        Some(a) => Ok(a),
-        None => get_default_address(),
+/// Prints the error with its causes, and what scripts and bug reports need if verbose
+fn print_problem(e: &NetLoadError, verbose: bool) {
+    eprintln!("error: {}", e);
+    for cause in causes(e) {
+        eprintln!("  caused by: {}", cause);
+    }
+    if !verbose {
+        return;
+    }
+    eprintln!("  kind: {} (exit code {})", e.kind(), e.exit_code());
+    if let NetLoadError::ConnectFailed(failures) = e {
+        for (sock_addr, _) in failures {
+            eprintln!("  tried: {}", sock_addr);
+        }
+    }
+    if let Ok(path) = get_config_path() {
+        eprintln!("  configuration: {}", path.display());
+    }
+    if var_os(DEBUG_VAR_NAME).is_some() {
+        eprintln!("{:#?}", e);
+    }
+}
+
+/// Prints the error as text or as a JSON object, then exits with its code
+fn print_problem_and_exit(e: &NetLoadError, json: bool, verbose: bool) -> ! {
+    if json {
+        LoadReport::default().print(Some(e))
+    } else {
+        print_problem(e, verbose);
+    }
+    exit(e.exit_code())
+}
+
+// ---------- Loading ----------
+
+/// What to do besides sending, only possible with a single Wii
+#[derive(Default)]
+struct SendOptions {
+    /// File to write every byte that goes through the socket to
+    dump_stream: Option<PathBuf>,
+    /// Stops right before connecting
+    dry_run: bool,
+    /// Prints sizes and speed once sent, under the progress bar
+    summary: bool,
+}
+
+impl SendOptions {
+    fn is_set(&self) -> bool {
+        self.dump_stream.is_some() || self.dry_run
     }
 }
 
-fn set_default_address(new: String) -> Result<(), DefaultAddressConfigError> {
-    let mut writer = File::create(get_config_path()?)?;
-    writer.write_all(&new.as_bytes())?;
+/// What went through the socket, and how fast
+fn transfer_summary(transfer: &Transfer) -> String {
+    format!(
+        "{} ({} uncompressed) in {:.1}s, {}/s",
+        format_size(transfer.compressed),
+        format_size(transfer.raw),
+        transfer.duration.as_secs_f64(),
+        format_size(transfer.throughput() as u64)
+    )
+}
 
+/// Lets the user know why nothing seems to happen while waiting to connect again
+fn print_retry(retry: &Retry) {
+    for (sock_addr, e) in retry.failures {
+        eprintln!("Could not connect to {} ({})", sock_addr, e);
+    }
+    eprintln!(
+        "Retrying in {:.1}s ({}/{})",
+        retry.delay.as_secs_f64(),
+        retry.attempt,
+        retry.retries
+    );
+}
+
+/// Warns about a configuration that could not be read, and tells where the address came from if verbose
+fn print_destination(destination: &Destination, verbose: bool) {
+    if let Some(e) = &destination.config_error {
+        eprintln!(
+            "warning: Could not read the configuration ({}), using built-in defaults",
+            e.kind()
+        );
+        for cause in successors(e.source(), |&c| c.source()) {
+            eprintln!("  caused by: {}", cause);
+        }
+    }
+    if let (true, Some(v)) = (verbose, &destination.ignored_environment) {
+        eprintln!(
+            "warning: {} is set to \"{}\", which is not a network address, ignoring it",
+            ENV_VAR_NAME, v
+        );
+    }
+    if verbose {
+        println!(
+            "Using address {} port {} (from {})",
+            destination.address, destination.port, destination.source
+        );
+        if destination.sock_addrs.len() > 1 {
+            for sock_addr in &destination.sock_addrs {
+                println!("Resolved to {}", sock_addr);
+            }
+        }
+    }
+}
+
+/// Sends to a single Wii, writing the stream to a file first if asked to
+fn send_one(
+    destination: &Destination,
+    payload: &Payload,
+    options: &SendOptions,
+    report: &mut LoadReport,
+    verbose: bool,
+) -> Result<(), NetLoadError> {
+    let encoded = destination.encode(payload)?;
+    if verbose {
+        match encoded.level {
+            Some(l) => println!("Compressed at level {}", l),
+            None => println!("Sending uncompressed"),
+        }
+    }
+    if let Some(path) = &options.dump_stream {
+        write(path, &encoded.stream).map_err(|e| NetLoadError::write_failed(path, e))?;
+    }
+    if options.dry_run {
+        report.dry_run = Some(DryRunReport::new(destination, payload, &encoded));
+        return Ok(());
+    }
+    let transfer = destination.transmit(&encoded.stream)?;
+    if options.summary {
+        println!("Sent {}", transfer_summary(&transfer));
+    }
+    report.record(&transfer);
     Ok(())
 }
 
-fn remove_config_files() -> Result<(), DefaultAddressConfigError> {
-    if let Result::Err(e) = remove_file(get_config_path()?) {
-        return match e.kind() {
-            IOErrorKind::NotFound => Err(DefaultAddressConfigError::NoConfiguredDefault),
-            _ => Err(DefaultAddressConfigError::FileAccess(e)),
+/// Reads then sends to every loader's destination, keeping track of what happened to each for --json.
+/// The payload is only read once, and compressed once for all destinations sharing the same settings.
+fn read_and_load(
+    source: &PayloadOptions,
+    loaders: &[Loader],
+    options: &SendOptions,
+    verbose: bool,
+) -> Vec<(Result<(), NetLoadError>, LoadReport)> {
+    let payload = match Payload::read(source) {
+        Ok(p) => p,
+        Err(e) => return vec![(Err(e), LoadReport::default())],
+    };
+    if let (true, Some(description)) = (verbose, &payload.description) {
+        let verb = if source.install {
+            "Installing"
+        } else {
+            "Sending"
+        };
+        println!("{} {}", verb, description);
+    }
+
+    // Progress bars of parallel uploads would get mixed up, only a single Wii gets one
+    if let [loader] = loaders {
+        let mut report = LoadReport {
+            file: Some(payload.name.clone()),
+            ..LoadReport::default()
         };
+        let result = loader.destination().and_then(|destination| {
+            print_destination(&destination, verbose);
+            report.target = Some(join_host_port(&destination.address, destination.port));
+            send_one(&destination, &payload, options, &mut report, verbose)
+        });
+        if report.target.is_none() {
+            report.target = loader.name().map(str::to_string);
+        }
+        return vec![(result, report)];
+    }
+    load_all(loaders, &payload, |d| print_destination(d, verbose))
+        .into_iter()
+        .map(|delivery| {
+            let mut report = LoadReport {
+                file: Some(payload.name.clone()),
+                target: delivery.target,
+                ..LoadReport::default()
+            };
+            let result = delivery.result.map(|t| report.record(&t));
+            (result, report)
+        })
+        .collect()
+}
+
+/// Sends a captured stream as is, checking it is a complete upload unless forced
+fn replay(
+    command: &ReplayCommand,
+    loader: &Loader,
+    report: &mut LoadReport,
+    verbose: bool,
+) -> Result<(), NetLoadError> {
+    let stream =
+        fsread(&command.stream).map_err(|e| NetLoadError::read_failed(&command.stream, e))?;
+    if !command.force {
+        let upload = read_upload(&mut stream.as_slice())
+            .map_err(|e| NetLoadError::invalid_stream(&command.stream, e))?;
+        report.file = upload.name().map(str::to_string);
     }
 
+    let destination = loader.destination()?;
+    print_destination(&destination, verbose);
+    report.target = Some(join_host_port(&destination.address, destination.port));
+    let transfer = destination.transmit(&stream)?;
+    if loader.progress {
+        println!("Sent {}", transfer_summary(&transfer));
+    }
+    report.record(&transfer);
     Ok(())
 }
 
-// ---------- Code for net loading ----------
+/// Prints what happened to each Wii, then exits with the code of the first failure if there is one
+fn print_results(results: Vec<(Result<(), NetLoadError>, LoadReport)>, json: bool, verbose: bool) {
+    let code = results
+        .iter()
+        .find_map(|(r, _)| r.as_ref().err())
+        .map(NetLoadError::exit_code);
+    let several = results.len() > 1;
+
+    for (result, report) in results {
+        if json {
+            report.print(result.as_ref().err());
+            continue;
+        }
+        let target = report.target.as_deref().unwrap_or_default();
+        match result {
+            Ok(()) if several => println!(
+                "{}: Sent {} in {:.1}s",
+                target,
+                format_size(report.bytes_sent.unwrap_or_default()),
+                report.duration.unwrap_or_default()
+            ),
+            Ok(()) => {
+                if let Some(dry_run) = &report.dry_run {
+                    dry_run.print(target);
+                }
+            }
+            Err(e) => {
+                if several {
+                    eprint!("{}: ", target);
+                }
+                print_problem(&e, verbose);
+            }
+        }
+    }
+    if let Some(code) = code {
+        exit(code)
+    }
+}
+
+// ---------- Watching ----------
 
-enum NetLoadError {
-    NoAddressPassed,
-    CantResolveAddress,
-    ArgsTooLong,
-    BinaryTooLong,
-    IOError(IOError),
-    OtherConfigError(DefaultAddressConfigError),
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(500); // Linkers may write the output in several steps
+const WATCH_MIN_RETRIES: u32 = 10; // HBC takes a few seconds to come back after the previous app exits
+
+/// Modification time and size, enough to tell two versions of a file apart
+type FileState = (SystemTime, u64);
+
+fn file_state(path: &Path) -> Option<FileState> {
+    let m = metadata(path).ok()?;
+    Some((m.modified().ok()?, m.len()))
 }
 
-impl From<WiiLoadFail> for NetLoadError {
-    fn from(r: WiiLoadFail) -> NetLoadError {
-        match r {
-            WiiLoadFail::ArgsTooLong => NetLoadError::ArgsTooLong,
-            WiiLoadFail::BinaryTooLong => NetLoadError::BinaryTooLong,
-            WiiLoadFail::NetError(e) => NetLoadError::IOError(e),
+/// Waits for the file to be different from last, then to stay the same for a while
+fn wait_for_change(path: &Path, last: Option<FileState>) -> FileState {
+    let mut candidate: Option<(FileState, Instant)> = None;
+    loop {
+        sleep(WATCH_POLL_INTERVAL);
+        let state = match file_state(path) {
+            Some(s) if Some(s) != last => s,
+            _ => {
+                candidate = None;
+                continue;
+            }
+        };
+        match candidate {
+            Some((c, since)) if c == state => {
+                if since.elapsed() >= WATCH_SETTLE_TIME {
+                    return state;
+                }
+            }
+            _ => candidate = Some((state, Instant::now())),
         }
     }
 }
 
-impl From<DefaultAddressConfigError> for NetLoadError {
-    fn from(r: DefaultAddressConfigError) -> NetLoadError {
-        match r {
-            DefaultAddressConfigError::NoConfiguredDefault => NetLoadError::NoAddressPassed,
-            _ => NetLoadError::OtherConfigError(r),
+fn do_watch(
+    source: PayloadOptions,
+    mut loaders: Vec<Loader>,
+    options: SendOptions,
+    json: bool,
+    verbose: bool,
+) -> ! {
+    for loader in &mut loaders {
+        loader.retries = loader.retries.max(WATCH_MIN_RETRIES);
+    }
+    let progress = loaders[0].progress;
+    let several = loaders.len() > 1;
+    // Each upload gets a single line with the time instead
+    let options = SendOptions {
+        summary: false,
+        ..options
+    };
+    let path = Path::new(&source.path);
+    let mut last = None;
+
+    loop {
+        if progress {
+            println!("Watching {} for changes...", source.path);
+        }
+        last = Some(wait_for_change(path, last));
+
+        for (result, report) in read_and_load(&source, &loaders, &options, verbose) {
+            if json {
+                report.print(result.as_ref().err());
+                continue;
+            }
+            let time = Local::now().format("%H:%M:%S");
+            let target = match (several, &report.target) {
+                (true, Some(t)) => format!("{}: ", t),
+                _ => String::new(),
+            };
+            match result {
+                Ok(()) => match &report.transfer {
+                    Some(t) => println!(
+                        "[{}] {}Sent {}, {}",
+                        time,
+                        target,
+                        source.path,
+                        transfer_summary(t)
+                    ),
+                    None => println!("[{}] {}Sent {}", time, target, source.path),
+                },
+                Err(e) => {
+                    eprint!("[{}] {}", time, target);
+                    print_problem(&e, verbose);
+                }
+            }
         }
     }
 }
 
-impl From<IOError> for NetLoadError {
-    fn from(r: IOError) -> NetLoadError {
-        NetLoadError::IOError(r)
+// ---------- Discovery ----------
+
+/// Address as it should be stored, omitting the port if it is the usual one
+fn responder_address(responder: &Responder) -> String {
+    if responder.port == TCP_PORT {
+        responder.address.to_string()
+    } else {
+        format!("{}:{}", responder.address, responder.port)
     }
 }
 
-impl NetLoadError {
-    fn print_problem_and_exit(&self) {
-        eprint!("error: ");
-        match self {
-            NetLoadError::NoAddressPassed => {
-                eprintln!("No address argument, but not default address configured, aborting.")
-            }
-            NetLoadError::CantResolveAddress => {
-                eprintln!("Cannot resolve passed address, aborting.")
+/// Asks which responder to save as the default address, if any
+fn pick_responder(responders: &[Responder]) -> Result<Option<&Responder>, IOError> {
+    // Even a single one replaces whatever address was saved before
+    if let [responder] = responders {
+        eprint!(
+            "Save {} as default address? [y/N] ",
+            responder_address(responder)
+        );
+    } else {
+        eprint!(
+            "Save which one as default address? [1-{}, empty to skip] ",
+            responders.len()
+        );
+    }
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+
+    if let [responder] = responders {
+        let yes = answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes");
+        return Ok(Some(responder).filter(|_| yes));
+    }
+    Ok(answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| i.checked_sub(1))
+        .and_then(|i| responders.get(i)))
+}
+
+fn do_discover(command: DiscoverCommand, json: bool) -> Result<(), NetLoadError> {
+    if !json {
+        eprintln!("Scanning local networks for port {}...", command.port);
+    }
+    let responders = discover(command.port, command.timeout)?;
+
+    if json {
+        // Printed once saved
+    } else if responders.is_empty() {
+        println!("No Wii found.");
+    } else {
+        for (i, responder) in responders.iter().enumerate() {
+            println!(
+                "{}. {} ({})",
+                i + 1,
+                responder_address(responder),
+                responder.interface
+            );
+        }
+    }
+
+    let mut saved = None;
+    if command.save && !responders.is_empty() {
+        if let Some(responder) = pick_responder(&responders)? {
+            let address = responder_address(responder);
+            set_default_address(address.clone())?;
+            eprintln!("Saved {} as default address.", address);
+            saved = Some(address);
+        }
+    }
+
+    if json {
+        #[derive(Serialize)]
+        struct Discovery<'a> {
+            responders: &'a [Responder],
+            /// Address saved as default, if one was picked
+            #[serde(skip_serializing_if = "Option::is_none")]
+            saved: Option<String>,
+        }
+        print_ok(Discovery {
+            responders: &responders,
+            saved,
+        });
+    }
+    Ok(())
+}
+
+// ---------- Receiving ----------
+
+/// Summary of an upload as printed with --json
+#[derive(Serialize)]
+struct ReceiveReport<'a> {
+    from: SocketAddr,
+    #[serde(flatten)]
+    upload: &'a Upload,
+    /// Where the file was written, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    saved_to: Option<PathBuf>,
+}
+
+/// Writes the file under the name it was sent with, and its arguments next to it
+fn save_upload(folder: &Path, upload: &Upload) -> Result<PathBuf, IOError> {
+    // Only keep the last component, the name comes from the network
+    let name = upload
+        .name()
+        .and_then(|n| Path::new(n).file_name())
+        .unwrap_or_else(|| OsStr::new("upload.bin"));
+    let path = folder.join(name);
+    let mut args_name = name.to_os_string();
+    args_name.push(".args");
+
+    create_dir_all(folder)?;
+    write(&path, &upload.data)?;
+    let args: String = upload.args.iter().map(|a| format!("{}\n", a)).collect();
+    write(folder.join(args_name), args)?;
+    Ok(path)
+}
+
+fn do_receive(command: ReceiveCommand, json: bool) -> Result<(), NetLoadError> {
+    let receiver = Receiver::bind((command.bind.as_str(), command.port))?;
+    if !json {
+        eprintln!("Listening on {}", receiver.local_addr()?);
+    }
+
+    loop {
+        let (from, upload) = match receiver.receive() {
+            Ok(r) => r,
+            // Whatever a client sends should not stop the server
+            Err(e) => {
+                let e = NetLoadError::IOError(IOError::new(IOErrorKind::InvalidData, e));
+                if command.once {
+                    return Err(e);
+                }
+                match json {
+                    true => LoadReport::default().print(Some(&e)),
+                    false => print_problem(&e, false),
+                }
+                continue;
             }
-            NetLoadError::ArgsTooLong => eprintln!("Arguments too long, aborting."),
-            NetLoadError::BinaryTooLong => eprintln!("Binary file too long, aborting."),
-            NetLoadError::IOError(e) => eprintln!("IO error, aborting. ({:?})", e.kind()),
-            NetLoadError::OtherConfigError(_) => {
-                eprintln!("Configuration-related error, aborting.")
+        };
+        let saved_to = match &command.output {
+            Some(folder) => Some(save_upload(folder, &upload)?),
+            None => None,
+        };
+
+        if json {
+            print_ok(ReceiveReport {
+                from,
+                upload: &upload,
+                saved_to,
+            });
+        } else {
+            println!(
+                "Received {} from {}, {} ({} sent), args: {:?}",
+                upload.name().unwrap_or("nameless upload"),
+                from,
+                format_size(upload.data.len() as u64),
+                format_size(upload.compressed_size as u64),
+                upload.args.get(1..).unwrap_or_default()
+            );
+            if let Some(path) = saved_to {
+                println!("Saved to {}", path.display());
             }
         }
-        exit(1)
+
+        if command.once {
+            return Ok(());
+        }
     }
 }
 
-const DEFAULT_COMPRESSION_LEVEL: u8 = 5; // Tuning this is pretty hard, but from quick testing this might be the best value
-const TCP_PORT: u16 = 4299; // Hard-coded in HBC ? Pointless to add an option to change it then.
+// ---------- Inspecting ----------
 
-// Perform the send operation
-fn do_net_load(
-    executable_path: String,
-    address: Option<String>,
-    compression: bool,
-) -> Result<(), NetLoadError> {
-    // Read file
-    let executable_data = fsread(executable_path)?;
-
-    // Connect to wii
-    // TODO: Simplify this ?
-    let to_connect_address = maybe_get_address(address)?;
-    let sock_addr: SocketAddr =
-        match format!("{}:{}", to_connect_address, TCP_PORT).to_socket_addrs() {
-            Ok(mut i) => match i.next() {
-                Some(v) => v,
-                None => return Err(NetLoadError::CantResolveAddress),
+fn print_dol(dol: &Dol) {
+    println!("Format: DOL");
+    println!("Section   Offset      Address     Size");
+    for (name, sections) in &[("text", &dol.text), ("data", &dol.data)] {
+        for section in sections.iter() {
+            println!(
+                "{:<9} {:#010X}  {:#010X}  {:#010X}",
+                format!("{}{}", name, section.index),
+                section.offset,
+                section.address,
+                section.size
+            );
+        }
+    }
+    if dol.bss_size > 0 {
+        println!(
+            "BSS: {:#010X}-{:#010X} ({})",
+            dol.bss_address,
+            dol.bss_address.saturating_add(dol.bss_size),
+            format_size(dol.bss_size as u64)
+        );
+    }
+}
+
+fn print_elf(elf: &Elf) {
+    println!("Format: ELF");
+    println!("Type     Offset      VirtAddr    PhysAddr    FileSize    MemSize     Flags");
+    for segment in &elf.segments {
+        println!(
+            "{:<8} {:#010X}  {:#010X}  {:#010X}  {:#010X}  {:#010X}  {}{}{}",
+            segment.kind_name(),
+            segment.offset,
+            segment.virtual_address,
+            segment.physical_address,
+            segment.file_size,
+            segment.memory_size,
+            if segment.flags & 4 != 0 { "R" } else { "-" },
+            if segment.flags & 2 != 0 { "W" } else { "-" },
+            if segment.flags & 1 != 0 { "X" } else { "-" },
+        );
+    }
+    for segment in elf.segments.iter().filter(|s| s.is_loaded()) {
+        if segment.memory_size > segment.file_size {
+            let start = segment.virtual_address.saturating_add(segment.file_size);
+            println!(
+                "BSS: {:#010X}-{:#010X} ({})",
+                start,
+                segment.virtual_address.saturating_add(segment.memory_size),
+                format_size((segment.memory_size - segment.file_size) as u64)
+            );
+        }
+    }
+}
+
+fn do_inspect(command: InspectCommand, json: bool) -> Result<(), NetLoadError> {
+    let data = fsread(&command.executable)
+        .map_err(|e| NetLoadError::read_failed(&command.executable, e))?;
+    let executable = Executable::parse(&data)
+        .map_err(|e| NetLoadError::invalid_executable(&command.executable, e))?;
+    let layout = executable.layout();
+
+    if json {
+        #[derive(Serialize)]
+        struct Inspection<'a> {
+            #[serde(flatten)]
+            executable: &'a Executable,
+            layout: Layout,
+        }
+        print_ok(Inspection {
+            executable: &executable,
+            layout,
+        });
+        return Ok(());
+    }
+
+    match &executable {
+        Executable::Dol(d) => print_dol(d),
+        Executable::Elf(e) => print_elf(e),
+    }
+    println!("Entry point: {:#010X}", executable.entry_point());
+    println!(
+        "Memory footprint: {:#010X}-{:#010X}, {} used",
+        layout.start,
+        layout.end,
+        format_size(layout.size)
+    );
+    println!(
+        "Runs on: {}",
+        if layout.uses_mem2 {
+            "Wii (uses MEM2)"
+        } else {
+            "GameCube or Wii (MEM1 only)"
+        }
+    );
+
+    Ok(())
+}
+
+// ---------- Benchmarking ----------
+
+/// Level as shown in the table
+fn level_name(level: Option<u8>) -> String {
+    match level {
+        Some(l) => l.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn do_bench(command: BenchCommand, json: bool, verbose: bool) -> Result<(), NetLoadError> {
+    let source = PayloadOptions {
+        path: command.executable,
+        name: None,
+        install: false,
+        force: command.force,
+        to_dol: false,
+    };
+    let payload = Payload::read(&source)?;
+    let verbose = verbose && !json;
+    if let (true, Some(description)) = (verbose, &payload.description) {
+        println!("Timing {}", description);
+    }
+
+    let measured = if command.address.is_some() || command.target.is_some() {
+        let loader = Loader {
+            address: command.address,
+            target: command.target.clone(),
+            port: command.port,
+            progress: !json,
+            on_retry: if json {
+                None
+            } else {
+                Some(Arc::new(print_retry))
             },
-            Err(_) => return Err(NetLoadError::CantResolveAddress),
+            ..Loader::default()
         };
-    let mut stream = TcpStream::connect(sock_addr)?;
-
-    // Actually send
-    net_send(
-        &mut stream,
-        &executable_data,
-        "".to_string(),
-        if compression {
-            Some(DEFAULT_COMPRESSION_LEVEL)
+        let destination = loader.destination()?;
+        print_destination(&destination, verbose);
+        Some(measure_link_speed(&destination, &payload)?)
+    } else {
+        None
+    };
+    let link_speed = match measured {
+        Some(s) => s,
+        None => configured_link_speed()?,
+    };
+
+    let timings = time_levels(&payload.data, link_speed);
+    // Level 0 only stores the data, as close to uncompressed as a level gets
+    let best = fastest(&timings).and_then(|t| t.level).unwrap_or(0);
+
+    if command.save {
+        let mut config = get_config()?;
+        match &command.target {
+            Some(name) => match config.targets.get_mut(name) {
+                Some(t) => t.compression_level = Some(best),
+                None => return Err(DefaultAddressConfigError::UnknownTarget(name.clone()).into()),
+            },
+            None => config.compression_level = Some(best),
+        }
+        if measured.is_some() {
+            config.link_speed = measured;
+        }
+        set_config(&config)?;
+    }
+
+    if json {
+        #[derive(Serialize)]
+        struct Bench<'a> {
+            /// In KiB/s
+            link_speed: u32,
+            /// Whether link_speed was measured rather than configured
+            measured: bool,
+            levels: &'a [LevelTiming],
+            recommended_level: u8,
+            saved: bool,
+        }
+        print_ok(Bench {
+            link_speed,
+            measured: measured.is_some(),
+            levels: &timings,
+            recommended_level: best,
+            saved: command.save,
+        });
+        return Ok(());
+    }
+
+    println!(
+        "Link speed: {} KiB/s ({})",
+        link_speed,
+        if measured.is_some() {
+            "measured"
         } else {
-            None
-        },
-    )?;
+            "configured"
+        }
+    );
+    println!("Level  Size        Compression  Transfer  Total");
+    for timing in &timings {
+        println!(
+            "{:<6} {:<11} {:<12} {:<9} {:.3}s",
+            level_name(timing.level),
+            format_size(timing.size as u64),
+            format!("{:.3}s", timing.compression_time),
+            format!("{:.3}s", timing.transfer_time),
+            timing.total_time
+        );
+    }
+    println!("Recommended level: {}", best);
+    if command.save {
+        match &command.target {
+            Some(name) => println!("Saved as the level of target \"{}\"", name),
+            None => println!("Saved as the default level"),
+        }
+    }
+    Ok(())
+}
+
+// ---------- Converting ----------
+
+fn do_convert(command: ConvertCommand, json: bool) -> Result<(), NetLoadError> {
+    let data = fsread(&command.executable)
+        .map_err(|e| NetLoadError::read_failed(&command.executable, e))?;
+    let dol =
+        elf_to_dol(&data).map_err(|e| NetLoadError::conversion_failed(&command.executable, e))?;
+    let output = match command.output {
+        Some(o) => o,
+        None => Path::new(&command.executable).with_extension("dol"),
+    };
+    write(&output, &dol).map_err(|e| NetLoadError::write_failed(&output, e))?;
 
+    // Already checked while converting
+    let executable = Executable::parse(&dol).unwrap();
+    if json {
+        #[derive(Serialize)]
+        struct Conversion<'a> {
+            output: &'a Path,
+            #[serde(flatten)]
+            executable: &'a Executable,
+        }
+        print_ok(Conversion {
+            output: &output,
+            executable: &executable,
+        });
+    } else {
+        println!("Wrote {}: {}", output.display(), executable);
+    }
     Ok(())
 }
 
 // ---------- Main Code ----------
 
-// Should just handle CLI-related stuff. Execute and print problem in case of an error.
-fn main() {
-    let opt = Commands::from_args();
+/// What a config command has to show once done
+struct Shown {
+    text: String,
+    /// Printed under "value" with --json
+    value: serde_json::Value,
+}
 
-    match opt {
-        // Load
-        Commands::Load(l) => {
-            if let Result::Err(e) = do_net_load(l.executable, l.address, !l.no_compression) {
-                e.print_problem_and_exit()
+/// Shows a configured value, or fails if it is not set
+fn setting<T: fmt::Display + Serialize>(
+    value: Option<T>,
+) -> Result<Option<Shown>, DefaultAddressConfigError> {
+    match value {
+        Some(v) => Ok(Some(Shown {
+            text: v.to_string(),
+            value: serde_json::to_value(&v).unwrap(),
+        })),
+        None => Err(DefaultAddressConfigError::NoConfiguredDefault),
+    }
+}
+
+fn config_target(command: ConfigTargetCommand) -> Result<Option<Shown>, DefaultAddressConfigError> {
+    let mut config = get_config()?;
+
+    match command {
+        // Add
+        ConfigTargetCommand::Add {
+            name,
+            address,
+            port,
+            level,
+            connect_timeout,
+            io_timeout,
+            groups,
+        } => config.add_target(
+            name,
+            Target {
+                address,
+                port,
+                compression_level: level,
+                connect_timeout: connect_timeout.map(|t| t.as_secs_f64()),
+                io_timeout: io_timeout.map(|t| t.as_secs_f64()),
+                groups,
+            },
+        )?,
+        // List
+        ConfigTargetCommand::List => {
+            let lines: Vec<String> = config
+                .targets
+                .iter()
+                .map(|(name, target)| {
+                    let marker = if config.default_target.as_ref() == Some(name) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    format!("{} {} {}", marker, name, target)
+                })
+                .collect();
+            return Ok(Some(Shown {
+                text: lines.join("\n"),
+                value: serde_json::json!({
+                    "default_target": config.default_target,
+                    "targets": config.targets,
+                }),
+            }));
+        }
+        // Remove
+        ConfigTargetCommand::Remove { name } => config.remove_target(&name)?,
+        // SetDefault
+        ConfigTargetCommand::SetDefault { name } => config.set_default_target(name)?,
+    }
+
+    set_config(&config)?;
+    Ok(None)
+}
+
+fn do_config(command: ConfigCommand) -> Result<Option<Shown>, DefaultAddressConfigError> {
+    match command {
+        // DefaultAddress
+        ConfigCommand::DefaultAddress(d) => match d {
+            // Set
+            ConfigDefaultAddressCommand::Set { address } => set_default_address(address)?,
+            // Get
+            ConfigDefaultAddressCommand::Get => return setting(Some(get_default_address()?)),
+        },
+        // Target
+        ConfigCommand::Target(t) => return config_target(t),
+        // CompressionLevel
+        ConfigCommand::CompressionLevel(l) => {
+            let mut config = get_config()?;
+            match l {
+                // Set
+                ConfigCompressionLevelCommand::Set { level } => {
+                    config.compression_level = Some(level);
+                    set_config(&config)?
+                }
+                // Get
+                ConfigCompressionLevelCommand::Get => return setting(config.compression_level()?),
             }
         }
-        // Config
-        Commands::Config(c) => match c {
-            // DefaultAddress
-            ConfigCommand::DefaultAddress(d) => match d {
+        // LinkSpeed
+        ConfigCommand::LinkSpeed(s) => {
+            let mut config = get_config()?;
+            match s {
+                // Set
+                ConfigLinkSpeedCommand::Set { speed } => {
+                    config.link_speed = Some(speed);
+                    set_config(&config)?
+                }
+                // Get
+                ConfigLinkSpeedCommand::Get => return setting(config.link_speed()?),
+            }
+        }
+        // ConnectTimeout
+        ConfigCommand::ConnectTimeout(t) => {
+            let mut config = get_config()?;
+            match t {
                 // Set
-                ConfigDefaultAddressCommand::Set { address } => {
-                    if let Result::Err(e) = set_default_address(address) {
-                        e.print_problem_and_exit()
-                    }
+                ConfigTimeoutCommand::Set { timeout } => {
+                    config.connect_timeout = Some(timeout.as_secs_f64());
+                    set_config(&config)?
                 }
                 // Get
-                ConfigDefaultAddressCommand::Get => match get_default_address() {
-                    Ok(a) => println!("{}", a),
-                    Err(e) => e.print_problem_and_exit(),
+                ConfigTimeoutCommand::Get => {
+                    return setting(config.connect_timeout()?.map(|t| t.as_secs_f64()))
+                }
+            }
+        }
+        // IoTimeout
+        ConfigCommand::IoTimeout(t) => {
+            let mut config = get_config()?;
+            match t {
+                // Set
+                ConfigTimeoutCommand::Set { timeout } => {
+                    config.io_timeout = Some(timeout.as_secs_f64());
+                    set_config(&config)?
+                }
+                // Get
+                ConfigTimeoutCommand::Get => {
+                    return setting(config.io_timeout()?.map(|t| t.as_secs_f64()))
+                }
+            }
+        }
+        // File
+        ConfigCommand::File(f) => match f {
+            // Delete
+            ConfigFileCommand::Delete => remove_config_files()?,
+            // PrintPath
+            ConfigFileCommand::PrintPath => {
+                return setting(Some(get_config_path()?.to_string_lossy()))
+            }
+        },
+    }
+    Ok(None)
+}
+
+/// Exits like clap would, but with the code EXIT_CODES documents for an invalid command line
+fn exit_usage(e: Error) -> ! {
+    // Help and version are not errors
+    if !e.use_stderr() {
+        e.exit()
+    }
+    eprintln!("{}", e.message);
+    exit(2)
+}
+
+// Should just handle CLI-related stuff. Execute and print problem in case of an error.
+fn main() {
+    let opt = Opt::from_args_safe().unwrap_or_else(|e| exit_usage(e));
+    let json = opt.json;
+    let verbose = opt.verbose;
+
+    match opt.command {
+        // Load
+        Commands::Load(l) => {
+            // Anything else on stdout would get in the way of the JSON
+            let (verbose, quiet) = match json {
+                true => (false, true),
+                false => (verbose, l.quiet),
+            };
+            let compression = if l.no_compression {
+                Compression::Disabled
+            } else if l.auto {
+                Compression::Auto
+            } else {
+                Compression::Level(l.level)
+            };
+            let loader = Loader {
+                port: l.port,
+                compression,
+                connect_timeout: l.connect_timeout,
+                io_timeout: l.io_timeout,
+                retries: l.retries,
+                retry_delay: l.retry_delay,
+                args: l.args,
+                progress: !quiet,
+                on_retry: if quiet {
+                    None
+                } else {
+                    Some(Arc::new(print_retry))
                 },
-            },
-            // File
-            ConfigCommand::File(f) => match f {
-                // Delete
-                ConfigFileCommand::Delete => {
-                    if let Result::Err(e) = remove_config_files() {
-                        e.print_problem_and_exit()
-                    }
+                // Address and target are set for each Wii by expand()
+                ..Loader::default()
+            };
+            let loaders = loader
+                .expand(l.addresses, l.targets, &l.groups, l.all_targets)
+                .unwrap_or_else(|e| print_problem_and_exit(&e, json, verbose));
+            let source = PayloadOptions {
+                path: l.executable,
+                name: l.name,
+                install: l.install,
+                force: l.force,
+                to_dol: l.to_dol,
+            };
+            let options = SendOptions {
+                dump_stream: l.dump_stream,
+                dry_run: l.dry_run,
+                summary: !quiet,
+            };
+            if options.is_set() && loaders.len() > 1 {
+                exit_usage(Error::with_description(
+                    "--dump-stream and --dry-run only work with a single Wii",
+                    ErrorKind::ArgumentConflict,
+                ))
+            }
+            if l.watch {
+                if is_stdin(Path::new(&source.path)) {
+                    exit_usage(Error::with_description(
+                        "Cannot watch stdin for changes, --watch needs a file",
+                        ErrorKind::ArgumentConflict,
+                    ))
                 }
-                // PrintPath
-                ConfigFileCommand::PrintPath => match get_config_path() {
-                    Ok(p) => println!("{}", p.to_string_lossy()),
-                    Err(e) => e.print_problem_and_exit(),
+                do_watch(source, loaders, options, json, verbose)
+            }
+            print_results(
+                read_and_load(&source, &loaders, &options, verbose),
+                json,
+                verbose,
+            )
+        }
+        // Run
+        Commands::Run(r) => {
+            let mut command = r.command.into_iter();
+            let executable = command.next().unwrap(); // At least one value is required
+            let loader = Loader {
+                target: r.target,
+                retries: r.retries,
+                args: command.collect(),
+                progress: !(r.quiet || json),
+                on_retry: if r.quiet || json {
+                    None
+                } else {
+                    Some(Arc::new(print_retry))
                 },
-            },
+                ..Loader::default()
+            };
+            let source = PayloadOptions {
+                path: executable,
+                name: None,
+                install: false,
+                force: false,
+                to_dol: r.to_dol,
+            };
+            print_results(
+                read_and_load(
+                    &source,
+                    &[loader],
+                    &SendOptions {
+                        summary: !(r.quiet || json),
+                        ..SendOptions::default()
+                    },
+                    verbose && !json,
+                ),
+                json,
+                verbose,
+            )
+        }
+        // Replay
+        Commands::Replay(r) => {
+            let loader = Loader {
+                address: r.address.clone(),
+                target: r.target.clone(),
+                port: r.port,
+                retries: r.retries,
+                progress: !(r.quiet || json),
+                on_retry: if r.quiet || json {
+                    None
+                } else {
+                    Some(Arc::new(print_retry))
+                },
+                ..Loader::default()
+            };
+            let mut report = LoadReport::default();
+            let result = replay(&r, &loader, &mut report, verbose && !json);
+            print_results(vec![(result, report)], json, verbose)
+        }
+        // Inspect
+        Commands::Inspect(i) => {
+            if let Result::Err(e) = do_inspect(i, json) {
+                print_problem_and_exit(&e, json, verbose)
+            }
+        }
+        // Bench
+        Commands::Bench(b) => {
+            if let Result::Err(e) = do_bench(b, json, verbose) {
+                print_problem_and_exit(&e, json, verbose)
+            }
+        }
+        // Convert
+        Commands::Convert(c) => {
+            if let Result::Err(e) = do_convert(c, json) {
+                print_problem_and_exit(&e, json, verbose)
+            }
+        }
+        // Receive
+        Commands::Receive(r) => {
+            if let Result::Err(e) = do_receive(r, json) {
+                print_problem_and_exit(&e, json, verbose)
+            }
+        }
+        // Discover
+        Commands::Discover(d) => {
+            if let Result::Err(e) = do_discover(d, json) {
+                print_problem_and_exit(&e, json, verbose)
+            }
+        }
+        // Config
+        Commands::Config(c) => match do_config(c) {
+            Ok(shown) if json => {
+                #[derive(Serialize)]
+                struct Value {
+                    value: serde_json::Value,
+                }
+                print_ok(shown.map(|s| Value { value: s.value }))
+            }
+            Ok(Some(shown)) if !shown.text.is_empty() => println!("{}", shown.text),
+            Ok(_) => {}
+            Err(e) => print_problem_and_exit(&NetLoadError::OtherConfigError(e), json, verbose),
         },
     }
 }
@@ -0,0 +1,204 @@
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+use zip::ZipArchive;
+use zip::ZipWriter;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::read as fsread;
+use std::fs::read_dir;
+use std::io::Cursor;
+use std::io::Error as IOError;
+use std::io::Write;
+use std::path::Path;
+
+// ---------- Homebrew Channel app packages ----------
+
+/// Executables HBC looks for in an app folder, in order of preference
+const BOOT_FILES: [&str; 2] = ["boot.dol", "boot.elf"];
+const META_FILE: &str = "meta.xml";
+
+#[derive(Debug)]
+pub enum PackageError {
+    /// Nothing under "apps/" in the package
+    NoApp,
+    /// Several folders under "apps/", HBC would install them all but only one is expected
+    SeveralApps(Vec<String>),
+    /// App folder has no boot.dol nor boot.elf
+    NoBootFile(String),
+    /// App folder has no meta.xml
+    NoMeta(String),
+    /// Directory name cannot be used as an app name
+    InvalidName(String),
+    Zip(ZipError),
+    IOError(IOError),
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackageError::NoApp => write!(f, "Package does not contain an \"apps/<name>/\" folder"),
+            PackageError::SeveralApps(names) => write!(
+                f,
+                "Package contains several apps ({}), expected only one",
+                names.join(", ")
+            ),
+            PackageError::NoBootFile(name) => write!(
+                f,
+                "App \"{}\" has neither {} nor {}",
+                name, BOOT_FILES[0], BOOT_FILES[1]
+            ),
+            PackageError::NoMeta(name) => write!(f, "App \"{}\" has no {}", name, META_FILE),
+            PackageError::InvalidName(name) => write!(f, "\"{}\" is not a valid app name", name),
+            PackageError::Zip(_) => write!(f, "Invalid ZIP file"),
+            PackageError::IOError(_) => write!(f, "Could not read the app"),
+        }
+    }
+}
+
+impl Error for PackageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PackageError::Zip(e) => Some(e),
+            PackageError::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ZipError> for PackageError {
+    fn from(e: ZipError) -> PackageError {
+        PackageError::Zip(e)
+    }
+}
+
+impl From<IOError> for PackageError {
+    fn from(e: IOError) -> PackageError {
+        PackageError::IOError(e)
+    }
+}
+
+/// ZIP file HBC can install to the SD card
+pub struct Package {
+    /// Name of the folder under "apps/"
+    pub name: String,
+    /// Contents of boot.dol or boot.elf, if any
+    pub boot: Option<Vec<u8>>,
+    /// Whether meta.xml is present
+    pub has_meta: bool,
+    /// The ZIP file itself, as it will be sent
+    pub data: Vec<u8>,
+}
+
+impl Package {
+    /// Makes sure HBC will find something to show and run once installed
+    pub fn check(&self) -> Result<(), PackageError> {
+        if self.boot.is_none() {
+            return Err(PackageError::NoBootFile(self.name.clone()));
+        }
+        if !self.has_meta {
+            return Err(PackageError::NoMeta(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Reads an existing ZIP, finding out which app it contains
+    pub fn from_zip(data: Vec<u8>) -> Result<Package, PackageError> {
+        let mut archive = ZipArchive::new(Cursor::new(&data))?;
+
+        let mut names: Vec<String> = archive
+            .file_names()
+            .filter_map(|n| app_name(n).map(str::to_string))
+            .collect();
+        names.sort();
+        names.dedup();
+        let name = match names.len() {
+            0 => return Err(PackageError::NoApp),
+            1 => names.remove(0),
+            _ => return Err(PackageError::SeveralApps(names)),
+        };
+
+        let mut boot = None;
+        for boot_file in BOOT_FILES.iter() {
+            if let Ok(mut file) = archive.by_name(&format!("apps/{}/{}", name, boot_file)) {
+                let mut contents = Vec::with_capacity(file.size() as usize);
+                std::io::copy(&mut file, &mut contents)?;
+                boot = Some(contents);
+                break;
+            }
+        }
+        let has_meta = archive
+            .by_name(&format!("apps/{}/{}", name, META_FILE))
+            .is_ok();
+
+        Ok(Package {
+            name,
+            boot,
+            has_meta,
+            data,
+        })
+    }
+
+    /// Zips an app folder such as "apps/myapp/" so that it extracts to "apps/myapp/" on the SD card
+    pub fn from_directory(path: &Path) -> Result<Package, PackageError> {
+        let name = match path.canonicalize()?.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None => return Err(PackageError::InvalidName(path.display().to_string())),
+        };
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        add_directory(&mut writer, path, &format!("apps/{}", name))?;
+        let data = writer.finish()?.into_inner();
+
+        let boot = BOOT_FILES
+            .iter()
+            .map(|b| path.join(b))
+            .find(|p| p.is_file())
+            .map(fsread)
+            .transpose()?;
+        let has_meta = path.join(META_FILE).is_file();
+
+        Ok(Package {
+            name,
+            boot,
+            has_meta,
+            data,
+        })
+    }
+}
+
+/// Name of the app an entry such as "apps/<name>/boot.dol" belongs to
+fn app_name(entry: &str) -> Option<&str> {
+    let mut parts = entry.trim_start_matches('/').splitn(3, '/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("apps"), Some(name), Some(_)) if !name.is_empty() => Some(name),
+        _ => None,
+    }
+}
+
+/// Recursively adds the contents of a directory, with paths starting with prefix
+fn add_directory<W: Write + std::io::Seek>(
+    writer: &mut ZipWriter<W>,
+    directory: &Path,
+    prefix: &str,
+) -> Result<(), PackageError> {
+    let mut entries = read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            writer.add_directory(format!("{}/", name), FileOptions::default())?;
+            add_directory(writer, &path, &name)?;
+        } else {
+            writer.start_file(
+                name,
+                FileOptions::default().compression_method(CompressionMethod::Deflated),
+            )?;
+            writer.write_all(&fsread(&path)?)?;
+        }
+    }
+    Ok(())
+}
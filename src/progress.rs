@@ -0,0 +1,154 @@
+use crate::receive::Header;
+use crate::receive::HEADER_LENGTH;
+
+use std::io::Result as IOResult;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+
+// ---------- Progress reporting while sending ----------
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a finished or interrupted transfer amounted to
+#[derive(Clone, Default)]
+pub struct Transfer {
+    /// Everything written to the socket, header and arguments included
+    pub sent: u64,
+    pub compressed: u64,
+    pub raw: u64,
+    pub duration: Duration,
+}
+
+impl Transfer {
+    /// Bytes per second
+    pub fn throughput(&self) -> f64 {
+        throughput(self.sent, self.duration)
+    }
+}
+
+/// Wraps the stream given to net_send, counting bytes as they are written and drawing a progress bar on stderr.
+pub struct ProgressWriter<W: Write> {
+    inner: W,
+    /// Only counts bytes if false
+    visible: bool,
+    /// First bytes written, until there are enough to parse
+    header_bytes: Vec<u8>,
+    header: Option<Header>,
+    sent: u64,
+    start: Option<Instant>,
+    last_draw: Option<Instant>,
+}
+
+impl<W: Write> ProgressWriter<W> {
+    pub fn new(inner: W, visible: bool) -> ProgressWriter<W> {
+        ProgressWriter {
+            inner,
+            visible,
+            header_bytes: Vec::with_capacity(HEADER_LENGTH),
+            header: None,
+            sent: 0,
+            start: None,
+            last_draw: None,
+        }
+    }
+
+    fn record(&mut self, written: &[u8]) {
+        let now = Instant::now();
+        let start = *self.start.get_or_insert(now);
+
+        if self.header_bytes.len() < HEADER_LENGTH {
+            let needed = (HEADER_LENGTH - self.header_bytes.len()).min(written.len());
+            self.header_bytes.extend_from_slice(&written[..needed]);
+            if self.header_bytes.len() == HEADER_LENGTH {
+                self.header = Header::parse(&self.header_bytes).ok();
+            }
+        }
+        self.sent += written.len() as u64;
+
+        let due = match self.last_draw {
+            Some(t) => now.duration_since(t) >= REDRAW_INTERVAL,
+            None => true,
+        };
+        if due {
+            self.draw(now.duration_since(start));
+            self.last_draw = Some(now);
+        }
+    }
+
+    fn draw(&self, elapsed: Duration) {
+        let header = match &self.header {
+            Some(h) if self.visible => h,
+            _ => return,
+        };
+        let total = header.upload_length();
+
+        let ratio = (self.sent as f64 / total as f64).min(1.0);
+        let filled = (ratio * BAR_WIDTH as f64) as usize;
+        let speed = throughput(self.sent, elapsed);
+        let eta = if speed > 0.0 {
+            format!("{:.0}s", total.saturating_sub(self.sent) as f64 / speed)
+        } else {
+            "?".to_string()
+        };
+
+        eprint!(
+            "\r[{}{}] {} / {} ({} raw) {}/s ETA {}   ",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            format_size(self.sent),
+            format_size(total),
+            format_size(header.data_length() as u64),
+            format_size(speed as u64),
+            eta
+        );
+    }
+
+    /// Ends the progress bar, returning what was sent if the header went through
+    pub fn finish(&self) -> Option<Transfer> {
+        let elapsed = self.start?.elapsed();
+        let transfer = self.header.as_ref().map(|header| Transfer {
+            sent: self.sent,
+            compressed: header.compressed_size as u64,
+            raw: header.data_length() as u64,
+            duration: elapsed,
+        });
+        if !self.visible {
+            return transfer;
+        }
+        self.draw(elapsed);
+        eprintln!();
+        transfer
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        self.record(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bytes per second
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        s if s > 0.0 => bytes as f64 / s,
+        _ => 0.0,
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
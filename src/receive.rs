@@ -0,0 +1,306 @@
+use miniz_oxide::inflate::core::decompress;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+use miniz_oxide::inflate::core::DecompressorOxide;
+use miniz_oxide::inflate::TINFLStatus;
+use serde::Serialize;
+
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+// ---------- Receiving uploads the way the HBC does ----------
+
+const MAGIC: &[u8; 4] = b"HAXX";
+/// Magic, version, args length, compressed size and uncompressed size
+pub const HEADER_LENGTH: usize = 16;
+/// Oldest protocol version that carries arguments, the one riiload speaks
+const MIN_VERSION: (u8, u8) = (0, 5);
+/// Nothing bigger than the Wii's memory could ever be loaded
+const MAX_SIZE: u32 = 0x0400_0000;
+/// Peers that stop sending are given up on after this long
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum ReceiveError {
+    /// Did not start with "HAXX"
+    BadMagic([u8; 4]),
+    UnsupportedVersion {
+        major: u8,
+        minor: u8,
+    },
+    /// Announced size is more than the Wii could hold
+    TooLarge(u32),
+    /// Payload is not valid zlib data
+    Decompression,
+    /// Payload does not have the size announced in the header, actual is None if it is larger
+    SizeMismatch {
+        expected: u32,
+        actual: Option<usize>,
+    },
+    /// Arguments are not a list of NUL-terminated UTF-8 strings
+    BadArguments,
+    /// More data came after the arguments
+    TrailingData(usize),
+    IOError(IOError),
+}
+
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReceiveError::BadMagic(m) => {
+                write!(f, "Upload starts with {:02X?} instead of \"HAXX\"", m)
+            }
+            ReceiveError::UnsupportedVersion { major, minor } => write!(
+                f,
+                "Protocol version {}.{} is not supported, expected at least {}.{}",
+                major, minor, MIN_VERSION.0, MIN_VERSION.1
+            ),
+            ReceiveError::TooLarge(s) => write!(f, "Announced size of {} bytes is too large", s),
+            ReceiveError::Decompression => write!(f, "Payload could not be decompressed"),
+            ReceiveError::SizeMismatch {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "Payload is {} bytes long once decompressed, header announced {}",
+                actual, expected
+            ),
+            ReceiveError::SizeMismatch {
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "Payload is more than the {} bytes announced once decompressed",
+                expected
+            ),
+            ReceiveError::BadArguments => {
+                write!(f, "Arguments are not NUL-terminated UTF-8 strings")
+            }
+            ReceiveError::TrailingData(n) => {
+                write!(f, "{} unexpected bytes after the arguments", n)
+            }
+            ReceiveError::IOError(_) => write!(f, "Could not receive the upload"),
+        }
+    }
+}
+
+impl Error for ReceiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReceiveError::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<IOError> for ReceiveError {
+    fn from(e: IOError) -> ReceiveError {
+        ReceiveError::IOError(e)
+    }
+}
+
+/// The 16 bytes every upload starts with
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Header {
+    pub major: u8,
+    pub minor: u8,
+    /// Length of the NUL-terminated arguments coming after the payload
+    pub args_length: u16,
+    /// Size of the payload as sent
+    pub compressed_size: u32,
+    /// Size once decompressed, 0 if sent uncompressed
+    pub raw_size: u32,
+}
+
+impl Header {
+    /// Header of the protocol version riiload speaks
+    pub fn new(args_length: u16, compressed_size: u32, raw_size: u32) -> Header {
+        Header {
+            major: MIN_VERSION.0,
+            minor: MIN_VERSION.1,
+            args_length,
+            compressed_size,
+            raw_size,
+        }
+    }
+
+    /// Reads the header the bytes start with, only checking the magic
+    pub fn parse(bytes: &[u8]) -> Result<Header, ReceiveError> {
+        let bytes = match bytes.get(..HEADER_LENGTH) {
+            Some(b) => b,
+            None => return Err(IOError::from(IOErrorKind::UnexpectedEof).into()),
+        };
+        if &bytes[..4] != MAGIC {
+            return Err(ReceiveError::BadMagic([
+                bytes[0], bytes[1], bytes[2], bytes[3],
+            ]));
+        }
+        let read_u32 = |at: usize| {
+            u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+
+        Ok(Header {
+            major: bytes[4],
+            minor: bytes[5],
+            args_length: u16::from_be_bytes([bytes[6], bytes[7]]),
+            compressed_size: read_u32(8),
+            raw_size: read_u32(12),
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), IOError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[self.major, self.minor])?;
+        writer.write_all(&self.args_length.to_be_bytes())?;
+        writer.write_all(&self.compressed_size.to_be_bytes())?;
+        writer.write_all(&self.raw_size.to_be_bytes())
+    }
+
+    /// Everything that goes through the socket, header and arguments included
+    pub fn upload_length(&self) -> u64 {
+        HEADER_LENGTH as u64 + self.compressed_size as u64 + self.args_length as u64
+    }
+
+    /// Size of the binary once decompressed by the Wii
+    pub fn data_length(&self) -> u32 {
+        match self.raw_size {
+            0 => self.compressed_size, // Not compressed
+            r => r,
+        }
+    }
+}
+
+/// Everything a client sent, as the Wii would see it
+#[derive(Serialize)]
+pub struct Upload {
+    pub major: u8,
+    pub minor: u8,
+    /// Size of the payload as sent
+    pub compressed_size: u32,
+    /// Size once decompressed, 0 if it was sent uncompressed
+    pub raw_size: u32,
+    /// argv, starting with the file name
+    pub args: Vec<String>,
+    /// Executable or ZIP, decompressed
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl Upload {
+    /// File name sent as argv[0]
+    pub fn name(&self) -> Option<&str> {
+        self.args.first().map(String::as_str)
+    }
+}
+
+/// Splits the NUL-terminated argument block
+/// Splits the NUL-terminated arguments coming after the payload
+pub fn parse_args(block: &[u8]) -> Result<Vec<String>, ReceiveError> {
+    if block.is_empty() {
+        return Ok(Vec::new());
+    }
+    let block = match block.split_last() {
+        Some((0, rest)) => rest,
+        _ => return Err(ReceiveError::BadArguments),
+    };
+    block
+        .split(|&b| b == 0)
+        .map(|a| String::from_utf8(a.to_vec()).map_err(|_| ReceiveError::BadArguments))
+        .collect()
+}
+
+/// Reads a whole upload, checking every length field, until the other end closes the connection
+pub fn read_upload<R: Read>(reader: &mut R) -> Result<Upload, ReceiveError> {
+    let mut bytes = [0; HEADER_LENGTH];
+    reader.read_exact(&mut bytes)?;
+
+    let Header {
+        major,
+        minor,
+        args_length,
+        compressed_size,
+        raw_size,
+    } = Header::parse(&bytes)?;
+    if (major, minor) < MIN_VERSION {
+        return Err(ReceiveError::UnsupportedVersion { major, minor });
+    }
+    if let Some(&s) = [compressed_size, raw_size].iter().find(|&&s| s > MAX_SIZE) {
+        return Err(ReceiveError::TooLarge(s));
+    }
+
+    let mut payload = vec![0; compressed_size as usize];
+    reader.read_exact(&mut payload)?;
+    let mut args = vec![0; args_length as usize];
+    reader.read_exact(&mut args)?;
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(ReceiveError::TrailingData(rest.len()));
+    }
+
+    let data = match raw_size {
+        0 => payload,
+        _ => {
+            // Never more than announced, a few bytes could otherwise take up all the memory
+            let mut data = vec![0; raw_size as usize];
+            let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+            let mut decompressor = Box::<DecompressorOxide>::default(); // Too large for the stack
+            let (status, _, length) = decompress(&mut decompressor, &payload, &mut data, 0, flags);
+            let actual = match status {
+                TINFLStatus::Done => Some(length),
+                TINFLStatus::HasMoreOutput => None,
+                _ => return Err(ReceiveError::Decompression),
+            };
+            if actual != Some(raw_size as usize) {
+                return Err(ReceiveError::SizeMismatch {
+                    expected: raw_size,
+                    actual,
+                });
+            }
+            data
+        }
+    };
+
+    Ok(Upload {
+        major,
+        minor,
+        compressed_size,
+        raw_size,
+        args: parse_args(&args)?,
+        data,
+    })
+}
+
+/// Listens for uploads like the HBC does, for testing without a console
+pub struct Receiver {
+    listener: TcpListener,
+}
+
+impl Receiver {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> Result<Receiver, IOError> {
+        Ok(Receiver {
+            listener: TcpListener::bind(address)?,
+        })
+    }
+
+    /// Useful when bound to port 0
+    pub fn local_addr(&self) -> Result<SocketAddr, IOError> {
+        self.listener.local_addr()
+    }
+
+    /// Waits for the next client and reads what it sends
+    pub fn receive(&self) -> Result<(SocketAddr, Upload), ReceiveError> {
+        let (mut stream, peer) = self.listener.accept()?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok((peer, read_upload(&mut stream)?))
+    }
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use riiload::bench::fastest;
+use riiload::bench::time_levels;
+
+#[test]
+fn every_level_is_timed() {
+    let data = common::dol();
+    let timings = time_levels(&data, 200);
+
+    let levels: Vec<_> = timings.iter().map(|t| t.level).collect();
+    assert_eq!(levels[0], None);
+    assert_eq!(levels[1..], (0..=9).map(Some).collect::<Vec<_>>()[..]);
+    assert_eq!(timings[0].size, data.len());
+    for timing in &timings {
+        assert_eq!(
+            timing.total_time,
+            timing.compression_time + timing.transfer_time
+        );
+    }
+}
+
+#[test]
+fn slow_link_favors_compression() {
+    // Repetitive enough to compress well
+    let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 16) as u8).collect();
+    let timings = time_levels(&data, 1);
+
+    let best = fastest(&timings).unwrap();
+    assert!(best.level.is_some());
+    assert!(timings.iter().all(|t| t.total_time >= best.total_time));
+}
@@ -0,0 +1,691 @@
+mod common;
+
+use std::fs::create_dir_all;
+use std::fs::read as fsread;
+use std::fs::write as fswrite;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+use std::thread;
+
+/// Runs the CLI with a configuration of its own, so that the user's defaults do not interfere.
+/// WIILOAD is only set if given in env.
+fn riiload(scratch: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_riiload"))
+        .args(args)
+        .env("XDG_CONFIG_HOME", scratch.join("config"))
+        .env("HOME", scratch)
+        .env_remove("WIILOAD")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap()
+}
+
+/// Port nothing listens on, most likely
+fn closed_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+#[test]
+fn load_reaches_receiver() {
+    let scratch = common::scratch_dir("load_reaches_receiver");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), &address, "--", "arg"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    // Summary under the progress bar
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Sent "), "{}", stdout);
+
+    let upload = handle.join().unwrap().unwrap();
+    assert_eq!(upload.data, common::dol());
+    assert_eq!(upload.args, ["test.dol", "arg"]);
+}
+
+#[test]
+fn auto_compression_reaches_receiver() {
+    let scratch = common::scratch_dir("auto_compression_reaches_receiver");
+    let path = scratch.join("large.bin");
+    // Large enough for levels to be compared, compressible enough for one to win
+    let data: Vec<u8> = (0..512 * 1024).map(|i| (i % 13) as u8).collect();
+    fswrite(&path, &data).unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            &address,
+            "--auto",
+            "-f",
+            "-q",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let upload = handle.join().unwrap().unwrap();
+    assert_eq!(upload.data, data);
+    assert_eq!(upload.raw_size as usize, data.len());
+    assert!((upload.compressed_size as usize) < data.len());
+}
+
+#[test]
+fn invalid_executable_is_refused() {
+    let scratch = common::scratch_dir("invalid_executable_is_refused");
+    let path = scratch.join("garbage.dol");
+    fswrite(&path, vec![0xAB; 0x400]).unwrap();
+
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), "127.0.0.1", "-p", &port],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(22));
+}
+
+#[test]
+fn refused_connection_is_reported() {
+    let scratch = common::scratch_dir("refused_connection_is_reported");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "--json",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(13));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["status"], "error");
+    assert_eq!(report["error"]["code"], 13);
+
+    let path = path.to_str().unwrap();
+    let output = riiload(
+        &scratch,
+        &["load", path, "127.0.0.1", "-p", &port, "-v"],
+        &[],
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("kind: connect_failed (exit code 13)"));
+    assert!(stderr.contains(&format!("tried: 127.0.0.1:{}", port)));
+    assert!(!stderr.contains("ConnectFailed"));
+}
+
+#[test]
+fn missing_address_is_reported() {
+    let scratch = common::scratch_dir("missing_address_is_reported");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let output = riiload(&scratch, &["load", path.to_str().unwrap()], &[]);
+    assert_eq!(output.status.code(), Some(10));
+}
+
+#[test]
+fn invalid_command_line_is_reported() {
+    let scratch = common::scratch_dir("invalid_command_line_is_reported");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let output = riiload(&scratch, &["load", path.to_str().unwrap(), "-l", "12"], &[]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), "-l", "9", "-n"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), "wii1", "wii2", "--dry-run"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let output = riiload(
+        &scratch,
+        &["config", "target", "add", "lab", "wii:99999"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let output = riiload(
+        &scratch,
+        &["config", "default-address", "set", "wii:port"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let output = riiload(&scratch, &["--help"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn usb_gecko_environment_falls_back_to_default() {
+    let scratch = common::scratch_dir("usb_gecko_environment_falls_back_to_default");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    // As set for devkitPro's wiiload
+    let gecko = [("WIILOAD", "/dev/ttyUSB0")];
+
+    let output = riiload(&scratch, &["load", path.to_str().unwrap()], &gecko);
+    assert_eq!(output.status.code(), Some(33));
+
+    let (address, handle) = common::spawn_receiver();
+    let output = riiload(
+        &scratch,
+        &["config", "default-address", "set", &address],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = riiload(&scratch, &["load", path.to_str().unwrap(), "-q"], &gecko);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(handle.join().unwrap().unwrap().data, common::dol());
+}
+
+#[test]
+fn legacy_address_is_migrated() {
+    let scratch = common::scratch_dir("legacy_address_is_migrated");
+    let legacy = scratch.join("config").join("riiload_config");
+    create_dir_all(scratch.join("config")).unwrap();
+    fswrite(&legacy, "192.168.1.20\n").unwrap();
+
+    let output = riiload(&scratch, &["config", "default-address", "get"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "192.168.1.20"
+    );
+    assert!(!legacy.exists());
+    assert!(scratch.join("config/riiload/config.toml").exists());
+}
+
+#[test]
+fn empty_legacy_file_is_removed() {
+    let scratch = common::scratch_dir("empty_legacy_file_is_removed");
+    let legacy = scratch.join("config").join("riiload_config");
+    create_dir_all(scratch.join("config")).unwrap();
+    fswrite(&legacy, "\n").unwrap();
+
+    let output = riiload(&scratch, &["config", "default-address", "get"], &[]);
+    assert_eq!(output.status.code(), Some(31));
+    assert!(!legacy.exists());
+    let output = riiload(
+        &scratch,
+        &["config", "default-address", "set", "1.2.3.4"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    // Anything else that is not an address is reported along with the file
+    fswrite(&legacy, "wii:port").unwrap();
+    std::fs::remove_dir_all(scratch.join("config").join("riiload")).unwrap();
+    let output = riiload(&scratch, &["config", "default-address", "get"], &[]);
+    assert_eq!(output.status.code(), Some(34));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("riiload_config"));
+}
+
+#[test]
+fn malformed_config_does_not_stop_given_address() {
+    let scratch = common::scratch_dir("malformed_config_does_not_stop_given_address");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let folder = scratch.join("config").join("riiload");
+    create_dir_all(&folder).unwrap();
+    fswrite(folder.join("config.toml"), "targets = [").unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), &address, "-q"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("warning"));
+    assert_eq!(handle.join().unwrap().unwrap().data, common::dol());
+
+    // Without an address, the configuration is needed
+    let output = riiload(&scratch, &["load", path.to_str().unwrap()], &[]);
+    assert_eq!(output.status.code(), Some(35));
+}
+
+#[test]
+fn malformed_config_can_be_deleted() {
+    let scratch = common::scratch_dir("malformed_config_can_be_deleted");
+    let folder = scratch.join("config").join("riiload");
+    create_dir_all(&folder).unwrap();
+    fswrite(folder.join("config.toml"), "targets = [").unwrap();
+    fswrite(scratch.join("config").join("riiload_config"), "wii").unwrap();
+
+    let output = riiload(&scratch, &["config", "file", "delete"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!folder.join("config.toml").exists());
+    assert!(!scratch.join("config").join("riiload_config").exists());
+
+    let output = riiload(&scratch, &["config", "file", "delete"], &[]);
+    assert_eq!(output.status.code(), Some(31));
+}
+
+#[test]
+fn invalid_target_level_is_reported() {
+    let scratch = common::scratch_dir("invalid_target_level_is_reported");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let folder = scratch.join("config").join("riiload");
+    create_dir_all(&folder).unwrap();
+    fswrite(
+        folder.join("config.toml"),
+        "[targets.wii]\naddress = \"127.0.0.1\"\ncompression_level = 12\n",
+    )
+    .unwrap();
+
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), "-t", "wii"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(34));
+}
+
+#[test]
+fn receive_saves_upload() {
+    let scratch = common::scratch_dir("receive_saves_upload");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let output_dir = scratch.join("received");
+
+    let port = closed_port().to_string();
+    let server = {
+        let (scratch, port, output_dir) = (scratch.clone(), port.clone(), output_dir.clone());
+        thread::spawn(move || {
+            riiload(
+                &scratch,
+                &[
+                    "receive",
+                    "-b",
+                    "127.0.0.1",
+                    "-p",
+                    &port,
+                    "-o",
+                    output_dir.to_str().unwrap(),
+                    "--once",
+                ],
+                &[],
+            )
+        })
+    };
+
+    // The server may take a moment to start listening
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "-q",
+            "--retries",
+            "20",
+            "--retry-delay",
+            "0.1",
+            "--",
+            "x",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.join().unwrap().status.code(), Some(0));
+
+    assert_eq!(fsread(output_dir.join("test.dol")).unwrap(), common::dol());
+    let args = String::from_utf8(fsread(output_dir.join("test.dol.args")).unwrap()).unwrap();
+    assert_eq!(args.lines().collect::<Vec<_>>(), ["test.dol", "x"]);
+}
+
+#[test]
+fn load_reaches_several_receivers() {
+    let scratch = common::scratch_dir("load_reaches_several_receivers");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let (mut addresses, handles): (Vec<_>, Vec<_>) =
+        (0..2).map(|_| common::spawn_receiver()).unzip();
+    addresses.push(format!("127.0.0.1:{}", closed_port()));
+
+    let mut args = vec!["load", path.to_str().unwrap(), "--json"];
+    args.extend(addresses.iter().map(String::as_str));
+    let output = riiload(&scratch, &args, &[]);
+    assert_eq!(output.status.code(), Some(13));
+
+    let reports: Vec<serde_json::Value> = output
+        .stdout
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.is_empty())
+        .map(|l| serde_json::from_slice(l).unwrap())
+        .collect();
+    let statuses: Vec<_> = reports.iter().map(|r| r["status"].clone()).collect();
+    assert_eq!(statuses, ["ok", "ok", "error"]);
+    for (report, address) in reports.iter().zip(&addresses) {
+        assert_eq!(report["target"], address.as_str());
+    }
+
+    for handle in handles {
+        assert_eq!(handle.join().unwrap().unwrap().data, common::dol());
+    }
+}
+
+#[test]
+fn run_forwards_trailing_args() {
+    let scratch = common::scratch_dir("run_forwards_trailing_args");
+    let path = scratch.join("app");
+    fswrite(&path, common::dol()).unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(&scratch, &["config", "target", "add", "wii", &address], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    // As Cargo would call a runner
+    let output = riiload(
+        &scratch,
+        &[
+            "run",
+            "-q",
+            "-t",
+            "wii",
+            path.to_str().unwrap(),
+            "--test",
+            "-q",
+            "x",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let upload = handle.join().unwrap().unwrap();
+    assert_eq!(upload.args, ["app", "--test", "-q", "x"]);
+}
+
+#[test]
+fn load_converts_to_dol() {
+    let scratch = common::scratch_dir("load_converts_to_dol");
+    let path = scratch.join("app.elf");
+    let segments = [common::Segment {
+        address: common::TEXT_ADDRESS,
+        executable: true,
+        contents: vec![0x60; 0x80],
+        bss: 0x40,
+    }];
+    let elf = common::elf(&segments, common::TEXT_ADDRESS);
+    fswrite(&path, &elf).unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(
+        &scratch,
+        &["load", path.to_str().unwrap(), &address, "-q", "--to-dol"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let upload = handle.join().unwrap().unwrap();
+    assert_eq!(upload.name(), Some("app.dol"));
+    assert_eq!(upload.data, riiload::executable::elf_to_dol(&elf).unwrap());
+}
+
+#[test]
+fn unwritable_conversion_is_reported() {
+    let scratch = common::scratch_dir("unwritable_conversion_is_reported");
+    let path = scratch.join("app.elf");
+    let segments = [common::Segment {
+        address: common::TEXT_ADDRESS,
+        executable: true,
+        contents: vec![0x60; 0x80],
+        bss: 0,
+    }];
+    fswrite(&path, common::elf(&segments, common::TEXT_ADDRESS)).unwrap();
+
+    let output = scratch.join("missing").join("app.dol");
+    let output = riiload(
+        &scratch,
+        &[
+            "convert",
+            path.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--json",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(27));
+}
+
+#[test]
+fn dumped_stream_replays() {
+    let scratch = common::scratch_dir("dumped_stream_replays");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let dump = scratch.join("session.bin");
+
+    // Nothing listens there, a dry run must not try to connect
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "--dry-run",
+            "--dump-stream",
+            dump.to_str().unwrap(),
+            "--",
+            "arg",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let stream = fsread(&dump).unwrap();
+    assert!(stream.starts_with(b"HAXX"));
+
+    let (address, handle) = common::spawn_receiver();
+    let output = riiload(
+        &scratch,
+        &["replay", dump.to_str().unwrap(), &address, "-q"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let upload = handle.join().unwrap().unwrap();
+    assert_eq!(upload.data, common::dol());
+    assert_eq!(upload.args, ["test.dol", "arg"]);
+    assert_eq!(upload.compressed_size as usize, stream.len() - 16 - 13);
+
+    fswrite(&dump, &stream[..stream.len() - 1]).unwrap();
+    let output = riiload(&scratch, &["replay", dump.to_str().unwrap(), &address], &[]);
+    assert_eq!(output.status.code(), Some(26));
+}
+
+#[test]
+fn unwritable_dump_is_reported() {
+    let scratch = common::scratch_dir("unwritable_dump_is_reported");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let dump = scratch.join("missing").join("session.bin");
+
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "--dry-run",
+            "--dump-stream",
+            dump.to_str().unwrap(),
+            "--json",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(27));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error"]["kind"], "write_failed");
+}
+
+#[test]
+fn dry_run_reports_upload() {
+    let scratch = common::scratch_dir("dry_run_reports_upload");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "-l",
+            "9",
+            "--dry-run",
+            "--json",
+            "--",
+            "arg",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let dry_run = &report["dry_run"];
+    assert_eq!(dry_run["address_source"], "command line argument");
+    assert_eq!(dry_run["level"], 9);
+    assert_eq!(dry_run["header"]["raw_size"], common::dol().len());
+    assert_eq!(
+        dry_run["header"]["args"],
+        serde_json::json!(["test.dol", "arg"])
+    );
+    assert_eq!(dry_run["args_length"], "test.dol\0arg\0".len());
+    assert!(report.get("bytes_sent").is_none());
+}
+
+#[test]
+fn bench_saves_measured_settings() {
+    let scratch = common::scratch_dir("bench_saves_measured_settings");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+
+    let (address, handle) = common::spawn_receiver();
+
+    let output = riiload(
+        &scratch,
+        &[
+            "bench",
+            path.to_str().unwrap(),
+            &address,
+            "--save",
+            "--json",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let upload = handle.join().unwrap().unwrap();
+    // Measuring sends uncompressed
+    assert_eq!(upload.raw_size, 0);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["measured"], true);
+    assert_eq!(report["levels"].as_array().unwrap().len(), 11);
+
+    let level = riiload(&scratch, &["config", "compression-level", "get"], &[]);
+    let level = String::from_utf8(level.stdout).unwrap();
+    assert_eq!(level.trim(), report["recommended_level"].to_string());
+    let speed = riiload(&scratch, &["config", "link-speed", "get"], &[]);
+    let speed = String::from_utf8(speed.stdout).unwrap();
+    assert_eq!(speed.trim(), report["link_speed"].to_string());
+}
+
+#[test]
+fn dry_run_handles_large_files() {
+    let scratch = common::scratch_dir("dry_run_handles_large_files");
+    let path = scratch.join("large.bin");
+    // More than any real upload could hold
+    fswrite(&path, vec![0; 0x0460_0000]).unwrap();
+
+    let port = closed_port().to_string();
+    let output = riiload(
+        &scratch,
+        &[
+            "load",
+            path.to_str().unwrap(),
+            "127.0.0.1",
+            "-p",
+            &port,
+            "--force",
+            "-n",
+            "--dry-run",
+            "--json",
+        ],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["dry_run"]["header"]["compressed_size"], 0x0460_0000);
+    assert_eq!(report["dry_run"]["header"]["raw_size"], 0);
+}
+
+#[test]
+fn every_command_prints_one_json_line() {
+    let scratch = common::scratch_dir("every_command_prints_one_json_line");
+    let path = scratch.join("test.dol");
+    fswrite(&path, common::dol()).unwrap();
+    let path = path.to_str().unwrap();
+
+    let commands: [&[&str]; 6] = [
+        &["config", "target", "add", "wii", "192.168.1.20"],
+        &["config", "target", "list"],
+        &["config", "link-speed", "get"],
+        &["config", "default-address", "get"],
+        &["inspect", path],
+        &["convert", path],
+    ];
+    let mut statuses = Vec::new();
+    for args in commands.iter() {
+        let args: Vec<&str> = args.iter().copied().chain(Some("--json")).collect();
+        let output = riiload(&scratch, &args, &[]);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.lines().count(), 1, "{:?}: {}", args, stdout);
+        let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+        statuses.push(report["status"].as_str().unwrap().to_string());
+        if args[1] == "target" && args[2] == "list" {
+            assert_eq!(report["value"]["targets"]["wii"]["address"], "192.168.1.20");
+        }
+    }
+    // Nothing configured for the link speed or as default, and a DOL cannot be converted
+    assert_eq!(statuses, ["ok", "ok", "error", "error", "ok", "error"]);
+}
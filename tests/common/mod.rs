@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use riiload::receive::ReceiveError;
+use riiload::receive::Receiver;
+use riiload::receive::Upload;
+
+use std::path::PathBuf;
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Address of the single text section of the DOL built by `dol()`
+pub const TEXT_ADDRESS: u32 = 0x8000_3100;
+const HEADER_LENGTH: usize = 0x100;
+const TEXT_SIZE: usize = 0x200;
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Smallest DOL riiload accepts: one text section, entry point at its start
+pub fn dol() -> Vec<u8> {
+    let mut data = vec![0; HEADER_LENGTH + TEXT_SIZE];
+    write_u32(&mut data, 0x00, HEADER_LENGTH as u32);
+    write_u32(&mut data, 0x48, TEXT_ADDRESS);
+    write_u32(&mut data, 0x90, TEXT_SIZE as u32);
+    write_u32(&mut data, 0xE0, TEXT_ADDRESS);
+    for (i, b) in data[HEADER_LENGTH..].iter_mut().enumerate() {
+        *b = (i % 251) as u8;
+    }
+    data
+}
+
+/// Loadable segment of the ELF built by `elf()`
+pub struct Segment {
+    pub address: u32,
+    /// Whether it holds code
+    pub executable: bool,
+    pub contents: Vec<u8>,
+    /// Zero-filled memory after the contents
+    pub bss: u32,
+}
+
+/// PowerPC executable ELF made of these segments
+pub fn elf(segments: &[Segment], entry_point: u32) -> Vec<u8> {
+    const HEADER_LENGTH: usize = 0x34;
+    const PROGRAM_HEADER_LENGTH: usize = 0x20;
+
+    let mut data = vec![0; HEADER_LENGTH + segments.len() * PROGRAM_HEADER_LENGTH];
+    data[..6].copy_from_slice(b"\x7FELF\x01\x02");
+    data[0x10..0x14].copy_from_slice(&[0, 2, 0, 20]); // ET_EXEC, EM_PPC
+    write_u32(&mut data, 0x18, entry_point);
+    write_u32(&mut data, 0x1C, HEADER_LENGTH as u32);
+    data[0x2A..0x2E].copy_from_slice(&[0, PROGRAM_HEADER_LENGTH as u8, 0, segments.len() as u8]);
+
+    for (i, segment) in segments.iter().enumerate() {
+        let at = HEADER_LENGTH + i * PROGRAM_HEADER_LENGTH;
+        let offset = data.len() as u32;
+        let size = segment.contents.len() as u32;
+        write_u32(&mut data, at, 1); // PT_LOAD
+        write_u32(&mut data, at + 0x04, offset);
+        write_u32(&mut data, at + 0x08, segment.address);
+        write_u32(&mut data, at + 0x0C, segment.address);
+        write_u32(&mut data, at + 0x10, size);
+        write_u32(&mut data, at + 0x14, size + segment.bss);
+        write_u32(&mut data, at + 0x18, if segment.executable { 5 } else { 6 });
+        data.extend_from_slice(&segment.contents);
+    }
+    data
+}
+
+/// Fresh directory for a single test to write into
+pub fn scratch_dir(test: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(test);
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Receiver on a free local port, taking a single upload from its own thread
+pub fn spawn_receiver() -> (String, JoinHandle<Result<Upload, ReceiveError>>) {
+    let receiver = Receiver::bind("127.0.0.1:0").unwrap();
+    let address = receiver.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || receiver.receive().map(|(_, upload)| upload));
+    (address, handle)
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::Segment;
+
+use riiload::executable::elf_to_dol;
+use riiload::executable::ConvertError;
+use riiload::executable::Executable;
+use riiload::executable::ExecutableError;
+
+fn segment(address: u32, executable: bool, length: usize, bss: u32) -> Segment {
+    Segment {
+        address,
+        executable,
+        contents: (0..length).map(|i| (i % 7) as u8 + 1).collect(),
+        bss,
+    }
+}
+
+#[test]
+fn segments_become_sections() {
+    let segments = [
+        segment(0x8000_4000, true, 0x123, 0),
+        segment(0x8001_0000, false, 0x45, 0x100),
+        segment(0x8002_0000, false, 0x10, 0x300),
+    ];
+    let dol = elf_to_dol(&common::elf(&segments, 0x8000_4010)).unwrap();
+
+    let parsed = match Executable::parse(&dol).unwrap() {
+        Executable::Dol(d) => d,
+        Executable::Elf(_) => panic!("conversion produced an ELF"),
+    };
+    assert_eq!(parsed.entry_point, 0x8000_4010);
+    assert_eq!(parsed.text.len(), 1);
+    assert_eq!(parsed.data.len(), 2);
+    // BSS spans from the end of the first data segment to the end of the last
+    assert_eq!(parsed.bss_address, 0x8001_0045);
+    assert_eq!(parsed.bss_size, 0x8002_0310 - 0x8001_0045);
+
+    let sections = parsed.text.iter().chain(&parsed.data);
+    for (section, segment) in sections.zip(&segments) {
+        assert_eq!(section.address, segment.address);
+        assert_eq!(section.offset % 32, 0);
+        let start = section.offset as usize;
+        assert_eq!(
+            &dol[start..start + section.size as usize],
+            segment.contents.as_slice()
+        );
+    }
+}
+
+#[test]
+fn too_many_code_segments_are_refused() {
+    let segments: Vec<_> = (0..8)
+        .map(|i| segment(0x8000_4000 + i * 0x1000, true, 0x20, 0))
+        .collect();
+    assert!(matches!(
+        elf_to_dol(&common::elf(&segments, 0x8000_4000)),
+        Err(ConvertError::TooManyTextSegments(8))
+    ));
+}
+
+#[test]
+fn too_many_data_segments_are_refused() {
+    let mut segments = vec![segment(0x8000_4000, true, 0x20, 0)];
+    segments.extend((0..12).map(|i| segment(0x8001_0000 + i * 0x1000, false, 0x20, 0)));
+    assert!(matches!(
+        elf_to_dol(&common::elf(&segments, 0x8000_4000)),
+        Err(ConvertError::TooManyDataSegments(12))
+    ));
+}
+
+#[test]
+fn entry_point_must_be_in_code() {
+    let segments = [
+        segment(0x8000_4000, true, 0x20, 0),
+        segment(0x8001_0000, false, 0x20, 0),
+    ];
+    assert!(matches!(
+        elf_to_dol(&common::elf(&segments, 0x8001_0000)),
+        Err(ConvertError::InvalidExecutable(
+            ExecutableError::BadEntryPoint(0x8001_0000)
+        ))
+    ));
+}
+
+#[test]
+fn dol_is_not_converted() {
+    assert!(matches!(
+        elf_to_dol(&common::dol()),
+        Err(ConvertError::NotElf)
+    ));
+}
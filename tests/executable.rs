@@ -0,0 +1,108 @@
+mod common;
+
+use common::Segment;
+
+use riiload::executable::Executable;
+use riiload::executable::ExecutableError;
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn rejection(data: &[u8]) -> ExecutableError {
+    match Executable::parse(data) {
+        Ok(_) => panic!("executable was accepted"),
+        Err(e) => e,
+    }
+}
+
+fn code(address: u32) -> Segment {
+    Segment {
+        address,
+        executable: true,
+        contents: vec![0x60; 0x100],
+        bss: 0,
+    }
+}
+
+#[test]
+fn dol_rejections() {
+    assert!(matches!(
+        rejection(&[0x12; 0x20]),
+        ExecutableError::UnknownFormat
+    ));
+
+    let mut dol = common::dol();
+    write_u32(&mut dol, 0x90, 0x1000); // Text section past the end of the file
+    assert!(matches!(rejection(&dol), ExecutableError::UnknownFormat));
+
+    let mut dol = common::dol();
+    write_u32(&mut dol, 0x48, 0x0200_0000);
+    assert!(matches!(
+        rejection(&dol),
+        ExecutableError::OutsideMemory(0x0200_0000)
+    ));
+
+    let mut dol = common::dol();
+    write_u32(&mut dol, 0x90, 0);
+    assert!(matches!(rejection(&dol), ExecutableError::NothingToLoad));
+
+    let mut dol = common::dol();
+    write_u32(&mut dol, 0xE0, 0x8000_0000);
+    assert!(matches!(
+        rejection(&dol),
+        ExecutableError::BadEntryPoint(0x8000_0000)
+    ));
+}
+
+#[test]
+fn elf_header_rejections() {
+    let elf = common::elf(&[code(0x8000_3100)], 0x8000_3100);
+    assert!(matches!(
+        rejection(&elf[..0x20]),
+        ExecutableError::Truncated
+    ));
+
+    let mut little_endian = elf.clone();
+    little_endian[5] = 1;
+    assert!(matches!(
+        rejection(&little_endian),
+        ExecutableError::WrongElfFlavor
+    ));
+
+    let mut x86 = elf.clone();
+    x86[0x12..0x14].copy_from_slice(&[0, 3]);
+    assert!(matches!(rejection(&x86), ExecutableError::WrongMachine(3)));
+
+    let mut object = elf;
+    object[0x10..0x12].copy_from_slice(&[0, 1]);
+    assert!(matches!(
+        rejection(&object),
+        ExecutableError::NotExecutable(1)
+    ));
+}
+
+#[test]
+fn elf_segment_rejections() {
+    let elf = common::elf(&[code(0x8000_3100)], 0x8000_3100);
+    assert!(matches!(
+        rejection(&elf[..elf.len() - 1]),
+        ExecutableError::Truncated
+    ));
+
+    let empty = common::elf(&[], 0x8000_3100);
+    assert!(matches!(rejection(&empty), ExecutableError::NothingToLoad));
+
+    // Right after the end of MEM1
+    let outside = common::elf(&[code(0x8180_0000)], 0x8180_0000);
+    assert!(matches!(
+        rejection(&outside),
+        ExecutableError::OutsideMemory(0x8180_0000)
+    ));
+
+    let bad_entry = common::elf(&[code(0x8000_3100)], 0x8000_3000);
+    assert!(matches!(
+        rejection(&bad_entry),
+        ExecutableError::BadEntryPoint(0x8000_3000)
+    ));
+}
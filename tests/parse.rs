@@ -0,0 +1,60 @@
+use riiload::join_host_port;
+use riiload::parse_seconds;
+use riiload::split_host_port;
+
+use std::time::Duration;
+
+#[test]
+fn seconds_are_parsed() {
+    assert_eq!(parse_seconds("2.5"), Ok(Duration::from_millis(2500)));
+}
+
+#[test]
+fn unusable_seconds_are_rejected() {
+    // Rounds down to nothing, or does not fit in a Duration
+    for s in &["0", "-1", "1e-12", "1e20", "inf", "NaN", "soon"] {
+        assert!(parse_seconds(s).is_err(), "{} was accepted", s);
+    }
+}
+
+fn host_port(host: &str, port: Option<u16>) -> Option<(String, Option<u16>)> {
+    Some((host.to_string(), port))
+}
+
+#[test]
+fn hosts_and_ports_are_split() {
+    assert_eq!(split_host_port("wii"), host_port("wii", None));
+    assert_eq!(split_host_port("wii:4300"), host_port("wii", Some(4300)));
+    assert_eq!(
+        split_host_port("10.0.0.2:4300"),
+        host_port("10.0.0.2", Some(4300))
+    );
+    assert_eq!(split_host_port("fe80::1"), host_port("fe80::1", None));
+    assert_eq!(split_host_port("[fe80::1]"), host_port("fe80::1", None));
+    assert_eq!(split_host_port("[::1]:4300"), host_port("::1", Some(4300)));
+}
+
+#[test]
+fn broken_addresses_are_rejected() {
+    for s in &[
+        ":4300",
+        "wii:",
+        "wii:port",
+        "wii:70000",
+        "[::1",
+        "[::1]4300",
+        "[::1]:",
+    ] {
+        assert_eq!(split_host_port(s), None, "{} was accepted", s);
+    }
+}
+
+#[test]
+fn ipv6_hosts_are_bracketed() {
+    assert_eq!(join_host_port("wii", 4299), "wii:4299");
+    assert_eq!(join_host_port("::1", 4299), "[::1]:4299");
+    assert_eq!(
+        split_host_port(&join_host_port("::1", 4299)),
+        host_port("::1", Some(4299))
+    );
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use riiload::config::Config;
+use riiload::receive::read_upload;
+use riiload::receive::Header;
+use riiload::receive::ReceiveError;
+use riiload::receive::Upload;
+use riiload::Compression;
+use riiload::Loader;
+use riiload::Payload;
+
+use std::io::Cursor;
+
+/// Sends payload to a local receiver with loader, returning what it got
+fn round_trip(loader: Loader, payload: &Payload) -> (riiload::progress::Transfer, Upload) {
+    let (address, handle) = common::spawn_receiver();
+
+    // Settings of the user's own configuration must not interfere
+    let loader = Loader {
+        address: Some(address),
+        config: Some(Config::default()),
+        ..loader
+    };
+    let transfer = loader.load(payload).unwrap();
+    (transfer, handle.join().unwrap().unwrap())
+}
+
+fn header(args_length: u16, compressed: u32, raw: u32) -> Vec<u8> {
+    let mut data = b"HAXX\x00\x05".to_vec();
+    data.extend_from_slice(&args_length.to_be_bytes());
+    data.extend_from_slice(&compressed.to_be_bytes());
+    data.extend_from_slice(&raw.to_be_bytes());
+    data
+}
+
+#[test]
+fn compressed_upload_arrives_intact() {
+    let payload = Payload::executable("test.dol", common::dol(), false).unwrap();
+    let loader = Loader {
+        args: vec!["a".to_string(), "b c".to_string()],
+        ..Loader::new("127.0.0.1")
+    };
+    let (transfer, upload) = round_trip(loader, &payload);
+
+    assert_eq!(upload.data, payload.data);
+    assert_eq!(upload.args, ["test.dol", "a", "b c"]);
+    assert_eq!(upload.name(), Some("test.dol"));
+    assert_eq!((upload.major, upload.minor), (0, 5));
+    assert_eq!(upload.raw_size as usize, payload.data.len());
+    assert_eq!(transfer.compressed, upload.compressed_size as u64);
+    let args_length = "test.dol\0a\0b c\0".len() as u64;
+    assert_eq!(transfer.sent, 16 + transfer.compressed + args_length);
+}
+
+#[test]
+fn uncompressed_upload_has_no_raw_size() {
+    let payload = Payload::executable("test.dol", common::dol(), false).unwrap();
+    let loader = Loader {
+        compression: Compression::Disabled,
+        ..Loader::new("127.0.0.1")
+    };
+    let (_, upload) = round_trip(loader, &payload);
+
+    assert_eq!(upload.raw_size, 0);
+    assert_eq!(upload.compressed_size as usize, payload.data.len());
+    assert_eq!(upload.data, payload.data);
+    assert_eq!(upload.args, ["test.dol"]);
+}
+
+#[test]
+fn header_round_trips() {
+    let header = Header::new(9, 1234, 5678);
+    let mut data = Vec::new();
+    header.write(&mut data).unwrap();
+    assert_eq!(data, self::header(9, 1234, 5678));
+    assert_eq!(Header::parse(&data).unwrap(), header);
+    assert_eq!(header.upload_length(), 16 + 1234 + 9);
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let mut data = header(0, 0, 0);
+    data[..4].copy_from_slice(b"HAXY");
+    match read_upload(&mut Cursor::new(data)) {
+        Err(ReceiveError::BadMagic(m)) => assert_eq!(&m, b"HAXY"),
+        r => panic!("unexpected result: {:?}", r.map(|u| u.args)),
+    }
+}
+
+#[test]
+fn old_protocol_is_rejected() {
+    let mut data = header(0, 0, 0);
+    data[5] = 4;
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::UnsupportedVersion { major: 0, minor: 4 })
+    ));
+}
+
+#[test]
+fn wrong_raw_size_is_rejected() {
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 6);
+    let mut data = header(0, compressed.len() as u32, 6);
+    data.extend_from_slice(&compressed);
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::SizeMismatch {
+            expected: 6,
+            actual: Some(5)
+        })
+    ));
+}
+
+#[test]
+fn oversized_payload_is_rejected() {
+    // Decompresses to far more than announced
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&[0; 1 << 20], 9);
+    let mut data = header(0, compressed.len() as u32, 16);
+    data.extend_from_slice(&compressed);
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::SizeMismatch {
+            expected: 16,
+            actual: None
+        })
+    ));
+}
+
+#[test]
+fn truncated_payload_is_rejected() {
+    let mut data = header(0, 10, 0);
+    data.extend_from_slice(b"short");
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::IOError(_))
+    ));
+}
+
+#[test]
+fn trailing_data_is_rejected() {
+    let mut data = header(3, 2, 0);
+    data.extend_from_slice(b"hiab\0extra");
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::TrailingData(5))
+    ));
+}
+
+#[test]
+fn unterminated_arguments_are_rejected() {
+    let mut data = header(3, 2, 0);
+    data.extend_from_slice(b"hiabc");
+    assert!(matches!(
+        read_upload(&mut Cursor::new(data)),
+        Err(ReceiveError::BadArguments)
+    ));
+}